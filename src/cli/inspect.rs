@@ -0,0 +1,137 @@
+use chrono::{DateTime, Utc};
+use clap::{ArgGroup, Args};
+
+use crate::{
+    model::{
+        account_summary::AccountSummary,
+        activity_summary::ActivitySummary,
+        currency::Currency,
+        error::spreadsheet_error::SpreadsheetError,
+        id::{client_id::ClientId, transaction_id::TransactionId},
+    },
+    store::Store,
+};
+
+use super::{read_input, DecimalArgs, InputArgs, Outcome, StoreArgs};
+
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("target").required(true).args(["client", "tx"])))]
+pub struct InspectArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub store: StoreArgs,
+
+    /// Show the balances, open disputes and activity of this client
+    #[arg(long)]
+    pub client: Option<u16>,
+
+    /// Also show the client's balances as of this time, such as `2024-05-01T00:00:00Z`
+    ///
+    /// Only rows with a `timestamp` are taken into account.
+    #[arg(long, requires = "client")]
+    pub as_of: Option<DateTime<Utc>>,
+
+    /// Show this transaction and its dispute history
+    #[arg(long)]
+    pub tx: Option<u32>,
+
+    #[command(flatten)]
+    pub decimals: DecimalArgs,
+}
+
+/// Applies every row, then prints what is known about a single client or transaction
+pub fn run(args: &InspectArgs) -> Result<Outcome, SpreadsheetError> {
+    let mut store = args.store.store()?;
+    if args.as_of.is_some() {
+        store = store.with_timeline();
+    }
+    let summary = read_input(&args.input, &mut store)?;
+
+    if let Some(client) = args.client.map(ClientId) {
+        match store.get_account(client)? {
+            Some(account) => {
+                let summary = AccountSummary::new(&account, &args.decimals.decimal_format());
+                println!("client:    {}", summary.client);
+                println!("available: {}", summary.available);
+                println!("held:      {}", summary.held);
+                println!("total:     {}", summary.total);
+                println!("locked:    {}", summary.locked);
+                let disputes = account
+                    .get_disputes()
+                    .iter()
+                    .map(|transaction| transaction.tx.to_string())
+                    .collect::<Vec<_>>();
+                println!("disputes:  {}", disputes.join(", "));
+                let activity = ActivitySummary::new(&account, &args.decimals.decimal_format());
+                println!("deposits:  {}", activity.deposits);
+                println!("withdrawn: {}", activity.withdrawals);
+                println!("received:  {}", activity.transfers_in);
+                println!("sent:      {}", activity.transfers_out);
+                println!("fees:      {}", activity.fees);
+                println!("interest:  {}", activity.interest);
+                if let Some(at) = args.as_of {
+                    print_balances_at(&store, client, at, &args.decimals);
+                }
+            }
+            None => println!("Client {} has no account", client),
+        }
+    }
+
+    if let Some(tx) = args.tx.map(TransactionId) {
+        match store.get_transactions().get(tx)? {
+            Some(record) => {
+                let transaction = &record.transaction;
+                let states = record
+                    .get_states()
+                    .iter()
+                    .map(|state| state.to_string())
+                    .collect::<Vec<_>>();
+                println!("tx:      {}", transaction.tx);
+                println!("type:    {}", transaction.transaction_type);
+                println!("client:  {}", transaction.client);
+                if let Some(amount) = transaction.amount {
+                    println!("amount:  {}", amount);
+                }
+                if let Some(destination) = transaction.destination {
+                    println!("to:      {}", destination);
+                }
+                if let (Some(rate), Some(converted)) =
+                    (transaction.rate, transaction.converted_amount())
+                {
+                    let to_currency = Currency::name(transaction.to_currency.as_ref());
+                    println!("rate:    {}", rate);
+                    println!("into:    {} {}", converted, to_currency);
+                }
+                println!("state:   {}", record.state());
+                println!("history: {}", states.join(" -> "));
+            }
+            None => println!("Transaction {} was not stored", tx),
+        }
+    }
+
+    Ok(Outcome::from(&summary))
+}
+
+/// Prints a client's balances in every currency as of a time
+fn print_balances_at(store: &Store, client: ClientId, at: DateTime<Utc>, decimals: &DecimalArgs) {
+    println!("as of:     {}", at.to_rfc3339());
+    let snapshot = match store.get_balances_at(client, at) {
+        Some(snapshot) => snapshot,
+        None => return println!("  no timestamped transactions by then"),
+    };
+    let format = decimals.decimal_format();
+    for (currency, balances) in snapshot {
+        let held = format.apply(balances.held);
+        let total = format.apply(balances.total);
+        println!(
+            "  {}: available {}, held {}, total {}, locked {}",
+            Currency::name(currency.as_ref()),
+            total - held,
+            held,
+            total,
+            balances.locked
+        );
+    }
+}
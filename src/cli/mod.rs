@@ -0,0 +1,514 @@
+//! The command line interface
+//!
+//! Each subcommand lives in its own module with its arguments and a `run` function.
+//! Options shared between subcommands are grouped into the `*Args` structs below.
+
+pub mod inspect;
+pub mod process;
+pub mod replay;
+pub mod source;
+pub mod validate;
+
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::PathBuf,
+};
+
+use chrono::Duration;
+use rust_decimal::Decimal;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+
+use source::Source;
+
+use crate::{
+    history::disk_history::DiskHistory,
+    model::{
+        account::Account,
+        currency_summary::CurrencySummary,
+        decimal_format::{DecimalFormat, Rounding, MAX_SCALE},
+        error::spreadsheet_error::SpreadsheetError,
+        policy::{
+            duplicate_policy::DuplicatePolicy, redispute_policy::RedisputePolicy,
+            withdrawal_dispute_policy::WithdrawalDisputePolicy,
+        },
+        rate_table::RateTable,
+    },
+    report::{ErrorReport, ReportFormat},
+    snapshot::load_snapshot,
+    spreadsheet::{
+        read_settings, read_spreadsheet, select_accounts, summarize, AccountOrder, Summary,
+    },
+    store::Store,
+};
+
+#[cfg(feature = "sqlite")]
+use crate::storage::sqlite_storage::SqliteStorage;
+
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Process transactions and output the final account balances
+    Process(process::ProcessArgs),
+    /// Check that every row can be parsed, without applying any transactions
+    Validate(validate::ValidateArgs),
+    /// Process transactions, outputting the affected account after every row
+    Replay(replay::ReplayArgs),
+    /// Process transactions, then show the details of one client or transaction
+    Inspect(inspect::InspectArgs),
+}
+
+/// Where transactions are read from and where problems with them are reported
+#[derive(Args, Debug)]
+pub struct InputArgs {
+    /// The csv files of transactions to process, in order
+    ///
+    /// Use `-` to read from standard input. Glob patterns such as `daily/*.csv` are expanded.
+    #[arg(required = true)]
+    pub inputs: Vec<String>,
+
+    /// Stop at the first row that can't be parsed, instead of reporting and skipping it
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Write rejected rows to this file instead of standard error
+    #[arg(long)]
+    pub error_report: Option<PathBuf>,
+
+    /// Format of the error report, defaults to `csv` for files and `text` for standard error
+    #[arg(long, value_enum)]
+    pub error_format: Option<ReportFormat>,
+
+    /// Hold back up to this many timestamped rows of each input to put them in time order
+    ///
+    /// Otherwise a row timestamped earlier than one before it is rejected.
+    #[arg(long, default_value = "0")]
+    pub reorder_window: usize,
+}
+
+impl InputArgs {
+    pub fn error_report(&self) -> io::Result<ErrorReport<Box<dyn Write>>> {
+        let (writer, default_format): (Box<dyn Write>, _) = match &self.error_report {
+            Some(path) => (Box::new(File::create(path)?), ReportFormat::Csv),
+            None => (Box::new(io::stderr()), ReportFormat::Text),
+        };
+        ErrorReport::new(writer, self.error_format.unwrap_or(default_format))
+    }
+
+    /// Reads each source in order with `read`, sharing one error report between them
+    ///
+    /// The combined summary is printed to standard error once every source is finished.
+    pub fn read_each<F>(&self, mut read: F) -> Result<Summary, SpreadsheetError>
+    where
+        F: FnMut(
+            &Source,
+            &mut csv::Reader<Box<dyn Read>>,
+            &mut ErrorReport<Box<dyn Write>>,
+        ) -> Result<Summary, SpreadsheetError>,
+    {
+        let sources = source::expand(&self.inputs)?;
+        let mut report = self.error_report()?;
+        let mut summary = Summary::default();
+        let mut result = Ok(());
+        for source in sources {
+            report.set_source(source.to_string());
+            result = source
+                .reader()
+                .map_err(SpreadsheetError::from)
+                .and_then(|mut reader| read(&source, &mut reader, &mut report))
+                .map(|source_summary| summary += source_summary);
+            if result.is_err() {
+                break;
+            }
+        }
+        report.into_inner()?;
+        result?;
+        eprintln!("{}", summary);
+        Ok(summary)
+    }
+}
+
+/// How the [Store] is set up
+#[derive(Args, Debug)]
+pub struct StoreArgs {
+    /// Keep the transaction history in this directory instead of in memory
+    #[arg(long)]
+    pub history_dir: Option<PathBuf>,
+
+    /// Keep accounts and transactions in this SQLite database instead of in memory
+    ///
+    /// Accounts and transactions already in the database are carried on from, and both can be
+    /// queried with SQL once processing is done.
+    #[cfg(feature = "sqlite")]
+    #[arg(long, conflicts_with = "history_dir")]
+    pub database: Option<PathBuf>,
+
+    /// What to do with deposits and withdrawals reusing a transaction id
+    #[arg(long, value_enum, default_value = "reject")]
+    pub duplicates: DuplicatePolicy,
+
+    /// Whether clients can dispute their withdrawals
+    #[arg(long, value_enum, default_value = "reject")]
+    pub withdrawal_disputes: WithdrawalDisputePolicy,
+
+    /// Whether a transaction can be disputed again after its dispute was resolved
+    #[arg(long, value_enum, default_value = "allow")]
+    pub redisputes: RedisputePolicy,
+
+    /// How far below zero a fee may take an account's available balance
+    #[arg(
+        long,
+        default_value = "0",
+        value_parser = non_negative,
+        allow_negative_numbers = true
+    )]
+    pub fee_limit: Decimal,
+
+    /// A csv file of per-client settings, with `client` and `overdraft_limit` columns
+    ///
+    /// When given, balances are output with `credit_limit` and `credit_used` columns.
+    #[arg(long)]
+    pub client_settings: Option<PathBuf>,
+
+    /// A csv file of exchange rates for `convert` rows, with `from`, `to`, `rate` and
+    /// `effective` columns
+    #[arg(long)]
+    pub rates: Option<PathBuf>,
+
+    /// Reject disputes filed more than this many days after the transaction they dispute
+    ///
+    /// Only applies to disputes and transactions that both have a `timestamp`.
+    #[arg(long, value_name = "DAYS")]
+    pub dispute_window: Option<u32>,
+
+    /// Resolve timestamped disputes still open this many days after they were filed
+    ///
+    /// Each is resolved once a later timestamped row is applied, and is kept in the audit log.
+    #[arg(long, value_name = "DAYS")]
+    pub dispute_expiry: Option<u32>,
+
+    /// Carry on from a snapshot saved by an earlier run with `--save-snapshot`
+    #[arg(long)]
+    pub load_snapshot: Option<PathBuf>,
+}
+
+impl StoreArgs {
+    pub fn store(&self) -> Result<Store, SpreadsheetError> {
+        let mut store = Store::new()
+            .with_duplicate_policy(self.duplicates)
+            .with_withdrawal_dispute_policy(self.withdrawal_disputes)
+            .with_redispute_policy(self.redisputes)
+            .with_fee_limit(self.fee_limit);
+        if let Some(days) = self.dispute_window {
+            store = store.with_dispute_window(Duration::days(days.into()));
+        }
+        if let Some(days) = self.dispute_expiry {
+            store = store.with_dispute_expiry(Duration::days(days.into()));
+        }
+        if let Some(history_dir) = &self.history_dir {
+            store = store.with_history(Box::new(DiskHistory::create(history_dir)?));
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(path) = &self.database {
+            store = store.with_storage(Box::new(SqliteStorage::open(path)?));
+        }
+        if let Some(path) = &self.client_settings {
+            let mut reader = Source::File(path.clone()).reader()?;
+            store = store.with_client_settings(read_settings(&mut reader)?);
+        }
+        if let Some(path) = &self.rates {
+            let mut reader = Source::File(path.clone()).reader()?;
+            store = store.with_rates(RateTable::new(read_settings(&mut reader)?));
+        }
+        if let Some(path) = &self.load_snapshot {
+            store = load_snapshot(store, path)?;
+        }
+        Ok(store)
+    }
+}
+
+/// How balances are rounded for output
+#[derive(Args, Debug)]
+pub struct DecimalArgs {
+    /// Number of decimal places every balance is printed with, at most 12
+    #[arg(
+        long,
+        default_value = "4",
+        value_parser = clap::value_parser!(u32).range(0..=i64::from(MAX_SCALE))
+    )]
+    pub precision: u32,
+
+    /// How balances are rounded to the chosen precision
+    #[arg(long, value_enum, default_value = "bankers")]
+    pub rounding: Rounding,
+}
+
+impl DecimalArgs {
+    pub fn decimal_format(&self) -> DecimalFormat {
+        DecimalFormat {
+            scale: self.precision,
+            rounding: self.rounding,
+        }
+    }
+}
+
+/// Where and how results are written
+#[derive(Args, Debug)]
+pub struct OutputArgs {
+    /// Write results to this file instead of standard output
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value = "csv")]
+    pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub decimals: DecimalArgs,
+
+    /// What accounts are sorted by, from smallest to largest
+    #[arg(long, value_enum, default_value = "client")]
+    pub sort: AccountOrder,
+
+    /// Sort from largest to smallest instead, accounts with equal balances stay in client order
+    #[arg(long)]
+    pub reverse: bool,
+
+    /// Only output locked accounts
+    #[arg(long)]
+    pub locked_only: bool,
+
+    /// Output one row for every currency of every account, with a `currency` column
+    ///
+    /// Otherwise only balances in the base currency are output.
+    #[arg(long)]
+    pub by_currency: bool,
+}
+
+impl OutputArgs {
+    pub fn writer(&self) -> io::Result<Box<dyn Write>> {
+        Ok(match &self.output {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(io::stdout()),
+        })
+    }
+
+    pub fn row_writer(&self) -> io::Result<RowWriter> {
+        Ok(self.format.row_writer(self.writer()?))
+    }
+
+    /// Writes one row for every selected account in the chosen format to `writer`
+    pub fn write_rows<S, F>(&self, store: &Store, writer: Box<dyn Write>, row: F) -> io::Result<()>
+    where
+        S: Serialize,
+        F: Fn(&Account, &DecimalFormat) -> S,
+    {
+        let mut writer = self.format.row_writer(writer);
+        let format = self.decimals.decimal_format();
+        for account in select_accounts(store, self.sort, self.reverse, self.locked_only)? {
+            writer.write(&row(&account, &format))?;
+        }
+        writer.flush()
+    }
+
+    /// Writes every account in the store in the chosen format
+    pub fn write_accounts(&self, store: &Store) -> io::Result<()> {
+        if !self.by_currency {
+            return self.write_rows(store, self.writer()?, |account, format| {
+                summarize(store, account, format)
+            });
+        }
+
+        let mut writer = self.row_writer()?;
+        let format = self.decimals.decimal_format();
+        for account in select_accounts(store, self.sort, self.reverse, self.locked_only)? {
+            for currency in account.get_currencies() {
+                writer.write(&CurrencySummary::new(&account, currency, &format))?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Csv,
+    /// One json object per line
+    Jsonl,
+}
+
+impl OutputFormat {
+    pub fn row_writer(self, writer: Box<dyn Write>) -> RowWriter {
+        match self {
+            OutputFormat::Csv => RowWriter::Csv(Box::new(csv::Writer::from_writer(writer))),
+            OutputFormat::Jsonl => RowWriter::Jsonl(writer),
+        }
+    }
+}
+
+/// Writes rows of output one at a time in an [OutputFormat]
+pub enum RowWriter {
+    Csv(Box<csv::Writer<Box<dyn Write>>>),
+    Jsonl(Box<dyn Write>),
+}
+
+impl RowWriter {
+    pub fn write<S: Serialize>(&mut self, row: &S) -> io::Result<()> {
+        match self {
+            RowWriter::Csv(writer) => Ok(writer.serialize(row)?),
+            RowWriter::Jsonl(writer) => {
+                serde_json::to_writer(&mut *writer, row)?;
+                writeln!(writer)
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            RowWriter::Csv(writer) => writer.flush(),
+            RowWriter::Jsonl(writer) => writer.flush(),
+        }
+    }
+}
+
+/// How a run ended, which decides the exit code
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// Every row was applied, exits with `0`
+    Success,
+    /// Some rows were rejected or malformed, exits with `1`
+    PartialFailure,
+    /// Processing could not finish, exits with `2`
+    Fatal,
+}
+
+impl Outcome {
+    pub fn code(self) -> i32 {
+        match self {
+            Outcome::Success => 0,
+            Outcome::PartialFailure => 1,
+            Outcome::Fatal => 2,
+        }
+    }
+}
+
+impl From<&Summary> for Outcome {
+    fn from(summary: &Summary) -> Outcome {
+        if summary.rejected > 0 || summary.malformed > 0 {
+            Outcome::PartialFailure
+        } else {
+            Outcome::Success
+        }
+    }
+}
+
+/// Runs the chosen subcommand, printing any fatal error to standard error
+pub fn run(cli: Cli) -> Outcome {
+    let result = match &cli.command {
+        Command::Process(args) => process::run(args),
+        Command::Validate(args) => validate::run(args),
+        Command::Replay(args) => replay::run(args),
+        Command::Inspect(args) => inspect::run(args),
+    };
+    match result {
+        Ok(outcome) => outcome,
+        Err(err) => {
+            eprintln!("{}", err);
+            Outcome::Fatal
+        }
+    }
+}
+
+/// Parses a limit, which can't be below zero
+fn non_negative(value: &str) -> Result<Decimal, String> {
+    let limit = value.parse::<Decimal>().map_err(|err| err.to_string())?;
+    if limit < Decimal::from(0) {
+        return Err(format!("{} is below zero", limit));
+    }
+    Ok(limit)
+}
+
+/// Reads every row of every input into the store, writing problems to the error report
+fn read_input(input: &InputArgs, store: &mut Store) -> Result<Summary, SpreadsheetError> {
+    input.read_each(|source, reader, report| {
+        store.set_source(source.to_string());
+        read_spreadsheet(store, reader, report, input.strict, input.reorder_window)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use clap::{error::ErrorKind, Parser};
+
+    use crate::test_util::temp_path;
+
+    use super::{run, Cli};
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("pseudo-bank").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn refuses_a_negative_fee_limit() {
+        assert!(parse(&["process", "in.csv", "--fee-limit", "0.5"]).is_ok());
+        let err = parse(&["process", "in.csv", "--fee-limit", "-1"]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ValueValidation);
+        assert_eq!(err.exit_code(), 2);
+    }
+
+    #[test]
+    fn refuses_a_precision_past_the_largest_scale() {
+        assert!(parse(&["process", "in.csv", "--precision", "12"]).is_ok());
+        let err = parse(&["process", "in.csv", "--precision", "13"]).unwrap_err();
+        assert_eq!(err.exit_code(), 2);
+    }
+
+    #[test]
+    fn recovers_a_run_stopped_by_a_malformed_row() {
+        let (input, journal) = (temp_path("cli-input.csv"), temp_path("cli-journal"));
+        let (output, report) = (temp_path("cli-output.csv"), temp_path("cli-report.csv"));
+        fs::write(
+            &input,
+            "type,client,tx,amount\ndeposit,1,1,10\ndeposit,1,2,5\noops,1,3,1\n",
+        )
+        .unwrap();
+        let args = [
+            "process",
+            input.to_str().unwrap(),
+            "--journal",
+            journal.to_str().unwrap(),
+            "--output",
+            output.to_str().unwrap(),
+            "--error-report",
+            report.to_str().unwrap(),
+        ];
+        let strict = [&args[..], &["--strict"]].concat();
+        assert_eq!(run(parse(&strict).unwrap()).code(), 2);
+        assert_eq!(run(parse(&args).unwrap()).code(), 1);
+        let balances = fs::read_to_string(&output).unwrap();
+        assert_eq!(
+            balances.lines().nth(1),
+            Some("1,15.0000,0.0000,15.0000,false")
+        );
+        for path in [input, journal, output, report] {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn refuses_a_journal_with_a_database() {
+        assert!(parse(&["process", "in.csv", "--database", "bank.db"]).is_ok());
+        let args = ["process", "in.csv", "--database", "db", "--journal", "j"];
+        assert_eq!(parse(&args).unwrap_err().exit_code(), 2);
+    }
+}
@@ -0,0 +1,95 @@
+use std::{fs::File, path::PathBuf};
+
+use clap::Args;
+
+use crate::{
+    journal::Journal,
+    model::{activity_summary::ActivitySummary, error::spreadsheet_error::SpreadsheetError},
+    pipeline::Pipeline,
+    snapshot::save_snapshot,
+};
+
+use super::{read_input, InputArgs, Outcome, OutputArgs, StoreArgs};
+
+#[derive(Args, Debug)]
+pub struct ProcessArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub store: StoreArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    /// Write every account lock and unlock to this file, in the output format
+    #[arg(long)]
+    pub audit_log: Option<PathBuf>,
+
+    /// Write the totals of each kind of transaction for every account to this file,
+    /// in the output format
+    #[arg(long)]
+    pub activity: Option<PathBuf>,
+
+    /// Save the state of every account and transaction to this file once processing is done,
+    /// for a later run to carry on from with `--load-snapshot`
+    #[arg(long)]
+    pub save_snapshot: Option<PathBuf>,
+
+    /// Journal every transaction to this file before applying it
+    ///
+    /// If a run is interrupted, running it again with the same inputs and journal recovers
+    /// the journaled transactions and carries on from the first row after them.
+    /// The journal is emptied once processing is done.
+    #[arg(long, conflicts_with = "reorder_window")]
+    #[cfg_attr(feature = "sqlite", arg(conflicts_with = "database"))]
+    pub journal: Option<PathBuf>,
+
+    /// Apply rows on this many threads, with each client's rows on the same thread
+    ///
+    /// The results are the same as with a single thread.
+    #[arg(
+        long,
+        default_value = "1",
+        value_parser = clap::value_parser!(u16).range(1..),
+        conflicts_with_all = ["journal", "history_dir", "dispute_expiry"]
+    )]
+    #[cfg_attr(feature = "sqlite", arg(conflicts_with = "database"))]
+    pub threads: u16,
+}
+
+/// Applies every row, then writes the final account balances
+pub fn run(args: &ProcessArgs) -> Result<Outcome, SpreadsheetError> {
+    let mut store = args.store.store()?;
+    if let Some(path) = &args.journal {
+        store = store.with_journal(Journal::open(path)?)?;
+    }
+    let summary = if args.threads > 1 {
+        let mut pipeline = Pipeline::new(&store, args.threads.into())?;
+        let summary = args.input.read_each(|_, reader, report| {
+            pipeline.read(reader, report, args.input.strict, args.input.reorder_window)
+        })?;
+        pipeline.finish(&mut store)?;
+        summary
+    } else {
+        read_input(&args.input, &mut store)?
+    };
+    args.output.write_accounts(&store)?;
+    if let Some(path) = &args.audit_log {
+        let mut writer = args.output.format.row_writer(Box::new(File::create(path)?));
+        for entry in store.get_audit_log() {
+            writer.write(entry)?;
+        }
+        writer.flush()?;
+    }
+    if let Some(path) = &args.activity {
+        let writer = Box::new(File::create(path)?);
+        args.output
+            .write_rows(&store, writer, ActivitySummary::new)?;
+    }
+    if let Some(path) = &args.save_snapshot {
+        save_snapshot(&store, path)?;
+    }
+    store.clear_journal()?;
+    Ok(Outcome::from(&summary))
+}
@@ -0,0 +1,98 @@
+use clap::Args;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{
+    model::{
+        currency::Currency,
+        currency_summary::CurrencySummary,
+        error::spreadsheet_error::SpreadsheetError,
+        id::{client_id::ClientId, transaction_id::TransactionId},
+        transaction_type::TransactionType,
+    },
+    spreadsheet::for_each_row,
+    store::InputOutcome,
+};
+
+use super::{InputArgs, Outcome, OutputArgs, StoreArgs};
+
+#[derive(Args, Debug)]
+pub struct ReplayArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub store: StoreArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+/// One step of a replay: a row of input and the state of its account afterwards,
+/// in the currency of the row
+#[derive(Debug, Serialize)]
+struct ReplayStep {
+    source: String,
+    line: u64,
+    #[serde(rename = "type")]
+    transaction_type: TransactionType,
+    client: ClientId,
+    tx: TransactionId,
+    amount: Option<Decimal>,
+    currency: Option<Currency>,
+    /// `ok`, or the name of the error the row was rejected with
+    result: &'static str,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+}
+
+/// Applies every row, writing the affected account's balances after each one
+pub fn run(args: &ReplayArgs) -> Result<Outcome, SpreadsheetError> {
+    let mut store = args.store.store()?;
+    let mut steps = args.output.row_writer()?;
+    let format = args.output.decimals.decimal_format();
+
+    let summary = args.input.read_each(|source, reader, report| {
+        let (strict, reorder_window) = (args.input.strict, args.input.reorder_window);
+        for_each_row(
+            reader,
+            report,
+            strict,
+            reorder_window,
+            |record, transaction| {
+                let (transaction_type, client, tx, amount, currency) = (
+                    transaction.transaction_type,
+                    transaction.client,
+                    transaction.tx,
+                    transaction.amount,
+                    transaction.currency,
+                );
+                let result = store.apply_transaction(transaction).map_err(|err| err.1);
+                let account = store
+                    .get_account(client)?
+                    .map(|account| CurrencySummary::new(&account, currency.as_ref(), &format));
+                steps.write(&ReplayStep {
+                    source: source.to_string(),
+                    line: record.position().map_or(0, |position| position.line()),
+                    transaction_type,
+                    client,
+                    tx,
+                    amount,
+                    currency,
+                    result: result.as_ref().map_or_else(|err| err.name(), |()| "ok"),
+                    available: account
+                        .as_ref()
+                        .map_or(0.into(), |account| account.available),
+                    held: account.as_ref().map_or(0.into(), |account| account.held),
+                    total: account.as_ref().map_or(0.into(), |account| account.total),
+                    locked: account.is_some_and(|account| account.locked),
+                })?;
+                Ok(result.map(|()| InputOutcome::Applied))
+            },
+        )
+    });
+    steps.flush()?;
+    Ok(Outcome::from(&summary?))
+}
@@ -0,0 +1,101 @@
+//! Where rows of input are read from
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read},
+    path::PathBuf,
+};
+
+use csv::ReaderBuilder;
+
+/// A single input, either a file or standard input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Stdin,
+    File(PathBuf),
+}
+
+impl Source {
+    pub fn reader(&self) -> io::Result<csv::Reader<Box<dyn Read>>> {
+        let reader: Box<dyn Read> = match self {
+            Source::Stdin => Box::new(io::stdin()),
+            Source::File(path) => Box::new(File::open(path)?),
+        };
+        Ok(ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader))
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Stdin => write!(f, "stdin"),
+            Source::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Turns command line arguments into sources, keeping their order
+///
+/// `-` means standard input. Arguments containing `*`, `?` or `[` are expanded as glob
+/// patterns into their matches in alphabetical order, and it is an error for one to match nothing.
+/// Anything else is taken as a path.
+pub fn expand(arguments: &[String]) -> io::Result<Vec<Source>> {
+    let mut sources = Vec::new();
+    for argument in arguments {
+        if argument == "-" {
+            sources.push(Source::Stdin);
+        } else if argument.contains(&['*', '?', '['][..]) {
+            let paths = glob::glob(argument)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(io::Error::from)?;
+            if paths.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No files match {}", argument),
+                ));
+            }
+            sources.extend(paths.into_iter().map(Source::File));
+        } else {
+            sources.push(Source::File(PathBuf::from(argument)));
+        }
+    }
+    Ok(sources)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::{expand, Source};
+    use crate::test_util::temp_path;
+
+    #[test]
+    fn expands_arguments_in_order() {
+        let directory = temp_path("sources");
+        fs::create_dir_all(&directory).unwrap();
+        for name in &["day-2.csv", "day-1.csv", "notes.txt"] {
+            fs::write(directory.join(name), "").unwrap();
+        }
+        let pattern = directory.join("day-*.csv").display().to_string();
+
+        let sources = expand(&["first.csv".to_string(), pattern, "-".to_string()]).unwrap();
+        assert_eq!(
+            sources,
+            vec![
+                Source::File(PathBuf::from("first.csv")),
+                Source::File(directory.join("day-1.csv")),
+                Source::File(directory.join("day-2.csv")),
+                Source::Stdin,
+            ]
+        );
+
+        let missing = directory.join("week-*.csv").display().to_string();
+        assert!(expand(&[missing]).is_err());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,19 @@
+use clap::Args;
+
+use crate::{model::error::spreadsheet_error::SpreadsheetError, spreadsheet::validate_spreadsheet};
+
+use super::{InputArgs, Outcome};
+
+#[derive(Args, Debug)]
+pub struct ValidateArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+}
+
+/// Parses every row without applying it, reporting the ones that are malformed
+pub fn run(args: &ValidateArgs) -> Result<Outcome, SpreadsheetError> {
+    let summary = args
+        .input
+        .read_each(|_, reader, report| validate_spreadsheet(reader, report, args.input.strict))?;
+    Ok(Outcome::from(&summary))
+}
@@ -0,0 +1,521 @@
+//! A [Store] that transactions can be applied to from many threads at once
+//!
+//! Clients are split between shards the same way as by the [pipeline](crate::pipeline), each
+//! shard a [Store] of its own behind a lock. Transactions of clients in different shards are
+//! applied at the same time, while each client's are applied one at a time.
+//!
+//! A few transactions depend on more than one shard:
+//! - a transfer to a client of another shard locks both shards
+//! - a transaction reusing the id of one that another shard has seen, or a timestamped
+//!   transaction earlier than one already handed to a shard, locks every shard
+//!
+//! Timestamped transactions also take the clock, which keeps them in time order across every
+//! shard, until they are journaled. Locks are always taken in the same order, the clock first
+//! and then shards by index, so that threads never end up waiting on each other.
+//!
+//! Transactions end up applied as if one at a time in a single order. With a
+//! [journal](ConcurrentStore::with_journal) that order is the journal's, so replaying the
+//! journal with a single [Store] gives the same accounts.
+//!
+//! As with the pipeline, a store with a [dispute expiry](Store::with_dispute_expiry) can't be
+//! split into shards.
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    io,
+    sync::{Mutex, MutexGuard},
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    journal::{Journal, JournalEntry},
+    model::{
+        account::Account,
+        audit_entry::AuditEntry,
+        error::transaction_error::TransactionError,
+        id::{client_id::ClientId, transaction_id::TransactionId},
+        transaction::Transaction,
+    },
+    pipeline::{shard_of, split, Seen, SHARED},
+    store::Store,
+};
+
+/// The shards of a [Store], shared between threads
+#[derive(Debug)]
+pub struct ConcurrentStore {
+    shards: Vec<Mutex<Store>>,
+    /// The latest timestamp handed to any shard
+    clock: Mutex<Option<DateTime<Utc>>>,
+    seen: Mutex<Seen>,
+    /// The sequence number of the first entry journaled since the store was split
+    journaled: u64,
+    /// The journal, and the sequence number of its next entry
+    journal: Option<Mutex<(Journal, u64)>>,
+    /// Audit entries made by every shard, each client's in the order they were made
+    audit_log: Mutex<Vec<AuditEntry>>,
+}
+
+impl ConcurrentStore {
+    /// Splits the store's accounts and transactions into the given number of shards
+    ///
+    /// Fails for a store with a [dispute expiry](Store::with_dispute_expiry), see
+    /// [split](crate::pipeline::split).
+    pub fn new(store: &Store, shards: usize) -> io::Result<ConcurrentStore> {
+        let (shards, seen) = split(store, shards)?;
+        Ok(ConcurrentStore {
+            shards,
+            clock: Mutex::new(store.get_latest()),
+            seen: Mutex::new(seen),
+            journaled: store.get_journaled(),
+            journal: None,
+            audit_log: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// See [Store::with_journal], entries are numbered on from those of the store it was
+    /// split from
+    ///
+    /// The journal should be empty or left by a concurrent store split from the same store.
+    pub fn with_journal(mut self, mut journal: Journal) -> io::Result<ConcurrentStore> {
+        let mut next = self.journaled;
+        for entry in journal.entries()? {
+            if entry.sequence < next {
+                continue;
+            }
+            let _ = self.apply_transaction(entry.transaction);
+            next = entry.sequence + 1;
+        }
+        self.journal = Some(Mutex::new((journal, next)));
+        Ok(self)
+    }
+
+    /// See [Store::clear_journal]
+    pub fn clear_journal(&self) -> io::Result<()> {
+        match &self.journal {
+            Some(journal) => lock(journal).0.clear(),
+            None => Ok(()),
+        }
+    }
+
+    /// Applies a transaction like [Store::apply_transaction], from any thread
+    #[allow(clippy::result_large_err)]
+    pub fn apply_transaction(
+        &self,
+        transaction: Transaction,
+    ) -> Result<(), (Transaction, TransactionError)> {
+        let shard = shard_of(transaction.client, self.shards.len());
+        let mut clock = transaction.timestamp.map(|_| lock(&self.clock));
+        let mut across = false;
+        if let (Some(clock), Some(timestamp)) = (&mut clock, transaction.timestamp) {
+            match **clock {
+                Some(latest) if timestamp < latest => across = true,
+                _ => **clock = Some(timestamp),
+            }
+        }
+        let destination = transaction
+            .destination
+            .map(|destination| (destination, shard_of(destination, self.shards.len())))
+            .filter(|(_, destination_shard)| *destination_shard != shard);
+
+        // Whether an id was seen by another shard can only be told with the shard locked
+        let mut shards = loop {
+            let shards = self.lock_shards(shard, destination, across);
+            if !transaction.transaction_type.has_amount() {
+                break shards;
+            }
+            let mut seen = lock(&self.seen);
+            let seen_by = *seen.entry(transaction.tx).or_insert(shard);
+            if seen_by != shard {
+                seen.insert(transaction.tx, SHARED);
+            }
+            if seen_by == shard || across {
+                break shards;
+            }
+            across = true;
+        };
+        let mut store = match shards.remove(&shard) {
+            Some(store) => store,
+            None => unreachable!("a transaction's own shard is always locked"),
+        };
+
+        if across {
+            if let Err(err) = self.catch_up(&mut store, &shards, transaction.tx) {
+                return Err((transaction, err.into()));
+            }
+        }
+        if let (Some(timestamp), Some(latest)) = (transaction.timestamp, store.get_latest()) {
+            if timestamp < latest {
+                return Err((
+                    transaction,
+                    TransactionError::OutOfOrder { timestamp, latest },
+                ));
+            }
+        }
+        if let Some(journal) = &self.journal {
+            if let Err(err) = transaction.validate() {
+                return Err((transaction, err));
+            }
+            let mut journal = lock(journal);
+            let (journal, sequence) = &mut *journal;
+            let entry = JournalEntry {
+                sequence: *sequence,
+                source: None,
+                offset: None,
+                transaction: transaction.clone(),
+            };
+            if let Err(err) = journal.append(&entry) {
+                return Err((transaction, err.into()));
+            }
+            *sequence += 1;
+        }
+        // Once journaled, later timestamped transactions can be handed to other shards
+        drop(clock);
+
+        let before = store.get_audit_log().len();
+        let result = match destination {
+            Some((destination, destination_shard)) => match shards.get_mut(&destination_shard) {
+                Some(destination_store) => {
+                    transfer(&mut store, destination_store, destination, transaction)
+                }
+                None => unreachable!("a transfer's destination shard is always locked"),
+            },
+            None => store.apply_transaction(transaction),
+        };
+        lock(&self.audit_log).extend_from_slice(&store.get_audit_log()[before..]);
+        result
+    }
+
+    /// Looks up a client's account
+    pub fn get_account(&self, client: ClientId) -> io::Result<Option<Account>> {
+        let shard = lock(&self.shards[shard_of(client, self.shards.len())]);
+        Ok(shard.get_account(client)?.map(Cow::into_owned))
+    }
+
+    /// Every account, ordered by client id, as of a moment no transaction was being applied
+    pub fn get_accounts(&self) -> io::Result<BTreeMap<ClientId, Account>> {
+        let shards = self.shards.iter().map(lock).collect::<Vec<_>>();
+        let mut accounts = BTreeMap::new();
+        for (index, shard) in shards.iter().enumerate() {
+            for (client, account) in shard.get_accounts()?.iter() {
+                if shard_of(*client, shards.len()) == index {
+                    accounts.insert(*client, account.clone());
+                }
+            }
+        }
+        Ok(accounts)
+    }
+
+    /// Audit entries made so far, each client's in the order they were made
+    pub fn get_audit_log(&self) -> Vec<AuditEntry> {
+        lock(&self.audit_log).clone()
+    }
+
+    /// Hands the shards back to `store` like [Pipeline](crate::pipeline::Pipeline::finish),
+    /// along with how far the journal got
+    ///
+    /// The journal is left as it is, to be cleared once the store is saved.
+    pub fn finish(self, store: &mut Store) -> io::Result<()> {
+        let journaled = self.journal.map_or(self.journaled, |journal| {
+            let (_, next) = journal.into_inner().unwrap_or_else(|err| err.into_inner());
+            next
+        });
+        store.advance_journaled(journaled);
+        let threads = self.shards.len();
+        for (index, shard) in self.shards.into_iter().enumerate() {
+            let shard = shard.into_inner().unwrap_or_else(|err| err.into_inner());
+            store.absorb(shard, |client| shard_of(client, threads) == index)?;
+        }
+        let audit_log = self
+            .audit_log
+            .into_inner()
+            .unwrap_or_else(|err| err.into_inner());
+        store.extend_audit_log(audit_log);
+        Ok(())
+    }
+
+    /// Locks the transaction's shard and the transfer destination's, or every shard
+    /// `across` them, in order of index
+    fn lock_shards(
+        &self,
+        shard: usize,
+        destination: Option<(ClientId, usize)>,
+        across: bool,
+    ) -> BTreeMap<usize, MutexGuard<'_, Store>> {
+        let indices: BTreeSet<usize> = if across {
+            (0..self.shards.len()).collect()
+        } else {
+            std::iter::once(shard)
+                .chain(destination.map(|(_, index)| index))
+                .collect()
+        };
+        indices
+            .into_iter()
+            .map(|index| (index, lock(&self.shards[index])))
+            .collect()
+    }
+
+    /// Brings a shard up to date with every other shard's latest timestamp, and the
+    /// transactions they have with the given id if it was seen by more than one shard
+    fn catch_up(
+        &self,
+        store: &mut Store,
+        others: &BTreeMap<usize, MutexGuard<'_, Store>>,
+        tx: TransactionId,
+    ) -> io::Result<()> {
+        if let Some(latest) = others.values().filter_map(|other| other.get_latest()).max() {
+            store.advance_to(latest);
+        }
+        if lock(&self.seen).get(&tx) == Some(&SHARED) {
+            for other in others.values() {
+                if let Some(record) = other.get_transactions().get(tx)? {
+                    store.share_transaction(&record)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Applies a transfer with the destination account brought over from its shard and back
+#[allow(clippy::result_large_err)]
+fn transfer(
+    store: &mut Store,
+    destination_store: &mut Store,
+    destination: ClientId,
+    transaction: Transaction,
+) -> Result<(), (Transaction, TransactionError)> {
+    let shared = destination_store
+        .get_account(destination)
+        .and_then(|account| match account {
+            Some(account) => store.share_account(&account, None),
+            None => Ok(()),
+        });
+    if let Err(err) = shared {
+        return Err((transaction, err.into()));
+    }
+    let copy = transaction.clone();
+    store.apply_transaction(transaction)?;
+    let returned = store
+        .get_account(destination)
+        .and_then(|account| match account {
+            Some(account) => destination_store.share_account(&account, copy.timestamp),
+            None => Ok(()),
+        });
+    match returned {
+        Ok(()) => Ok(()),
+        Err(err) => Err((copy, err.into())),
+    }
+}
+
+/// Locks a mutex, carrying on with what a panicking thread left behind
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread};
+
+    use chrono::{DateTime, Duration};
+
+    use crate::{
+        journal::Journal,
+        model::{
+            id::{client_id::ClientId, transaction_id::TransactionId},
+            transaction::Transaction,
+            transaction_type::TransactionType,
+        },
+        store::Store,
+        test_util::temp_path,
+    };
+
+    use super::ConcurrentStore;
+
+    const THREADS: u32 = 4;
+    const ROWS: u32 = 500;
+
+    /// A pseudo-random mix of one thread's transactions
+    ///
+    /// When `shared`, the thread's clients are shared with every other thread, and transfers,
+    /// reused ids and timestamps tie the threads together. Otherwise the thread has clients
+    /// and transaction ids of its own.
+    fn transactions(seed: u64, thread: u32, shared: bool) -> Vec<Transaction> {
+        let mut state = seed * 31 + u64::from(thread) + 1;
+        let mut next = move |bound: u32| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) as u32 % bound
+        };
+        let client = |pick: u32| {
+            if shared {
+                ClientId((pick % 10) as u16 + 1)
+            } else {
+                ClientId((pick % 3 * THREADS + thread) as u16 + 1)
+            }
+        };
+        (0..ROWS)
+            .map(|row| {
+                let own_tx = thread * ROWS + row + 1;
+                let (transaction_type, tx, amount) = match next(100) {
+                    0..=39 => (TransactionType::Deposit, own_tx, Some(next(50) + 1)),
+                    40..=59 => (TransactionType::Withdraw, own_tx, Some(next(30) + 1)),
+                    60..=79 if shared => (TransactionType::Transfer, own_tx, Some(next(20) + 1)),
+                    60..=79 => (TransactionType::Deposit, own_tx, Some(next(5) + 1)),
+                    80..=84 if shared => (TransactionType::Deposit, next(40) + 1, Some(1)),
+                    80..=89 => (
+                        TransactionType::Dispute,
+                        thread * ROWS + next(row + 1) + 1,
+                        None,
+                    ),
+                    90..=96 => (
+                        TransactionType::Resolve,
+                        thread * ROWS + next(row + 1) + 1,
+                        None,
+                    ),
+                    _ => (
+                        TransactionType::Chargeback,
+                        thread * ROWS + next(row + 1) + 1,
+                        None,
+                    ),
+                };
+                let timestamp = (shared && next(2) == 0).then(|| {
+                    let seconds = i64::from(row * THREADS + thread) + i64::from(next(8));
+                    DateTime::from_timestamp(1_700_000_000 + seconds, 0).unwrap()
+                });
+                Transaction {
+                    transaction_type,
+                    client: client(next(1000)),
+                    tx: TransactionId(tx),
+                    amount: amount.map(Into::into),
+                    destination: (transaction_type == TransactionType::Transfer)
+                        .then(|| client(next(1000))),
+                    currency: None,
+                    to_currency: None,
+                    rate: None,
+                    reason: None,
+                    timestamp,
+                }
+            })
+            .collect()
+    }
+
+    /// Applies each thread's transactions on a thread of its own
+    fn run(store: &ConcurrentStore, seed: u64, shared: bool) -> (usize, usize) {
+        thread::scope(|scope| {
+            let threads = (0..THREADS)
+                .map(|thread| {
+                    scope.spawn(move || {
+                        let mut rejected = 0;
+                        for transaction in transactions(seed, thread, shared) {
+                            if store.apply_transaction(transaction).is_err() {
+                                rejected += 1;
+                            }
+                        }
+                        rejected
+                    })
+                })
+                .collect::<Vec<_>>();
+            let rejected = threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .sum::<usize>();
+            ((THREADS * ROWS) as usize - rejected, rejected)
+        })
+    }
+
+    #[test]
+    fn is_shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<ConcurrentStore>();
+    }
+
+    #[test]
+    fn matches_a_sequential_replay_of_its_journal() {
+        for seed in 0..3 {
+            let path = temp_path("concurrent");
+            let store = ConcurrentStore::new(&Store::new(), 3)
+                .unwrap()
+                .with_journal(Journal::open(&path).unwrap())
+                .unwrap();
+            let (applied, rejected) = run(&store, seed, true);
+            assert!(applied > 0 && rejected > 0);
+
+            let replay = Store::new()
+                .with_journal(Journal::open(&path).unwrap())
+                .unwrap();
+            let accounts = serde_json::to_string(&store.get_accounts().unwrap()).unwrap();
+            let replayed = serde_json::to_string(&replay.get_accounts().unwrap()).unwrap();
+            assert_eq!(accounts, replayed);
+            assert_eq!(store.get_audit_log().len(), replay.get_audit_log().len());
+
+            let mut finished = Store::new();
+            store.finish(&mut finished).unwrap();
+            let finished = serde_json::to_string(&finished.get_accounts().unwrap()).unwrap();
+            assert_eq!(finished, replayed);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn matches_applying_each_threads_clients_in_turn() {
+        for seed in 0..3 {
+            let store = ConcurrentStore::new(&Store::new(), 3).unwrap();
+            run(&store, seed, false);
+
+            let mut sequential = Store::new();
+            for thread in 0..THREADS {
+                for transaction in transactions(seed, thread, false) {
+                    let _ = sequential.apply_transaction(transaction);
+                }
+            }
+            let accounts = serde_json::to_string(&store.get_accounts().unwrap()).unwrap();
+            let expected = serde_json::to_string(&sequential.get_accounts().unwrap()).unwrap();
+            assert_eq!(accounts, expected);
+        }
+    }
+
+    #[test]
+    fn carries_on_from_a_restored_snapshot() {
+        let path = temp_path("concurrent-snapshot");
+        let mut first = Store::new();
+        let store = ConcurrentStore::new(&first, 3)
+            .unwrap()
+            .with_journal(Journal::open(&path).unwrap())
+            .unwrap();
+        run(&store, 0, true);
+        store.finish(&mut first).unwrap();
+        assert!(first.get_journaled() > 0);
+
+        // A run that died after saving its snapshot, but before clearing the journal
+        let mut second = Store::new().restore(first.snapshot().unwrap()).unwrap();
+        let store = ConcurrentStore::new(&second, 3)
+            .unwrap()
+            .with_journal(Journal::open(&path).unwrap())
+            .unwrap();
+        let accounts = serde_json::to_string(&store.get_accounts().unwrap()).unwrap();
+        let expected = serde_json::to_string(&first.get_accounts().unwrap()).unwrap();
+        assert_eq!(accounts, expected);
+        run(&store, 1, false);
+        store.finish(&mut second).unwrap();
+
+        let replay = Store::new()
+            .restore(first.snapshot().unwrap())
+            .unwrap()
+            .with_journal(Journal::open(&path).unwrap())
+            .unwrap();
+        let accounts = serde_json::to_string(&second.get_accounts().unwrap()).unwrap();
+        let replayed = serde_json::to_string(&replay.get_accounts().unwrap()).unwrap();
+        assert_eq!(accounts, replayed);
+        assert_eq!(second.get_journaled(), replay.get_journaled());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_store_with_a_dispute_expiry() {
+        let store = Store::new().with_dispute_expiry(Duration::days(30));
+        assert!(ConcurrentStore::new(&store, 2).is_err());
+    }
+}
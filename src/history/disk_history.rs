@@ -0,0 +1,293 @@
+use std::{
+    convert::TryInto,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::model::{id::transaction_id::TransactionId, transaction_record::TransactionRecord};
+
+use super::TransactionHistory;
+
+const LOG_FILE: &str = "transactions.log";
+const INDEX_FILE: &str = "transactions.idx";
+const INITIAL_CAPACITY: u64 = 1 << 16;
+const BUCKET_SIZE: u64 = 16;
+
+/// Keeps transactions on disk so that memory usage stays fixed regardless of input size
+///
+/// Transaction records are appended as json lines to a log file. An open-addressing hash table,
+/// also stored on disk, maps each transaction id to the position of its latest row in the log.
+/// Lookups cost a few small reads, which the operating system's page cache keeps cheap for
+/// recently used parts of the files.
+///
+/// Each index bucket is 16 bytes: the transaction id, the row length and the row offset.
+/// A row length of zero marks an empty bucket. The table doubles in size once it is half full.
+#[derive(Debug)]
+pub struct DiskHistory {
+    directory: PathBuf,
+    log: File,
+    log_len: u64,
+    index: File,
+    capacity: u64,
+    len: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tx: TransactionId,
+    len: u32,
+    offset: u64,
+}
+
+impl Bucket {
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn to_bytes(self) -> [u8; BUCKET_SIZE as usize] {
+        let mut bytes = [0; BUCKET_SIZE as usize];
+        bytes[0..4].copy_from_slice(&self.tx.0.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.len.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.offset.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; BUCKET_SIZE as usize]) -> Bucket {
+        Bucket {
+            tx: TransactionId(u32::from_le_bytes(bytes[0..4].try_into().unwrap())),
+            len: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            offset: u64::from_le_bytes(bytes[8..16].try_into().unwrap()),
+        }
+    }
+}
+
+impl DiskHistory {
+    /// Creates an empty history in the given directory, discarding any history already there
+    pub fn create<P: AsRef<Path>>(directory: P) -> io::Result<DiskHistory> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+        let log = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(directory.join(LOG_FILE))?;
+        let index = create_index(&directory.join(INDEX_FILE), INITIAL_CAPACITY)?;
+        Ok(DiskHistory {
+            directory,
+            log,
+            log_len: 0,
+            index,
+            capacity: INITIAL_CAPACITY,
+            len: 0,
+        })
+    }
+
+    /// Finds the bucket holding `tx`, or the empty bucket where it would be inserted
+    fn find_bucket(&self, tx: TransactionId) -> io::Result<(u64, Bucket)> {
+        let mut position = slot(tx, self.capacity);
+        loop {
+            let bucket = read_bucket(&self.index, position)?;
+            if bucket.is_empty() || bucket.tx == tx {
+                return Ok((position, bucket));
+            }
+            position = (position + 1) % self.capacity;
+        }
+    }
+
+    /// Reads the log row a bucket points to
+    fn read_record(&self, bucket: Bucket) -> io::Result<TransactionRecord> {
+        let mut row = vec![0; bucket.len as usize];
+        (&self.log).seek(SeekFrom::Start(bucket.offset))?;
+        (&self.log).read_exact(&mut row)?;
+        Ok(serde_json::from_slice(&row)?)
+    }
+
+    /// Rebuilds the index with twice the capacity
+    fn grow(&mut self) -> io::Result<()> {
+        let capacity = self.capacity * 2;
+        let path = self.directory.join(INDEX_FILE);
+        let new_path = path.with_extension("idx.tmp");
+        let index = create_index(&new_path, capacity)?;
+
+        (&self.index).seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(&self.index);
+        let mut bytes = [0; BUCKET_SIZE as usize];
+        for _ in 0..self.capacity {
+            reader.read_exact(&mut bytes)?;
+            let bucket = Bucket::from_bytes(&bytes);
+            if bucket.is_empty() {
+                continue;
+            }
+            let mut position = slot(bucket.tx, capacity);
+            while !read_bucket(&index, position)?.is_empty() {
+                position = (position + 1) % capacity;
+            }
+            write_bucket(&index, position, bucket)?;
+        }
+
+        fs::rename(&new_path, &path)?;
+        self.index = index;
+        self.capacity = capacity;
+        Ok(())
+    }
+}
+
+impl TransactionHistory for DiskHistory {
+    fn get(&self, tx: TransactionId) -> io::Result<Option<TransactionRecord>> {
+        let (_, bucket) = self.find_bucket(tx)?;
+        if bucket.is_empty() {
+            return Ok(None);
+        }
+
+        self.read_record(bucket).map(Some)
+    }
+
+    fn records(&self) -> io::Result<Vec<TransactionRecord>> {
+        let mut records = Vec::with_capacity(self.len);
+        for position in 0..self.capacity {
+            let bucket = read_bucket(&self.index, position)?;
+            if bucket.is_empty() {
+                continue;
+            }
+            records.push(self.read_record(bucket)?);
+        }
+        Ok(records)
+    }
+
+    fn insert(&mut self, record: &TransactionRecord) -> io::Result<()> {
+        if (self.len as u64 + 1) * 2 > self.capacity {
+            self.grow()?;
+        }
+
+        let mut row = serde_json::to_vec(record)?;
+        row.push(b'\n');
+
+        (&self.log).seek(SeekFrom::Start(self.log_len))?;
+        (&self.log).write_all(&row)?;
+
+        let tx = record.transaction.tx;
+        let (position, existing) = self.find_bucket(tx)?;
+        let bucket = Bucket {
+            tx,
+            len: row.len() as u32,
+            offset: self.log_len,
+        };
+        write_bucket(&self.index, position, bucket)?;
+
+        self.log_len += row.len() as u64;
+        if existing.is_empty() {
+            self.len += 1;
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// Fibonacci hashing spreads sequential transaction ids across the table: multiplying by
+/// `2^64` over the golden ratio mixes every bit of the id into the high bits of the product,
+/// which are then scaled down to the capacity
+fn slot(tx: TransactionId, capacity: u64) -> u64 {
+    let hash = (tx.0 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    ((u128::from(hash) * u128::from(capacity)) >> 64) as u64
+}
+
+fn create_index(path: &Path, capacity: u64) -> io::Result<File> {
+    let index = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    index.set_len(capacity * BUCKET_SIZE)?;
+    Ok(index)
+}
+
+fn read_bucket(index: &File, position: u64) -> io::Result<Bucket> {
+    let mut bytes = [0; BUCKET_SIZE as usize];
+    let mut index = index;
+    index.seek(SeekFrom::Start(position * BUCKET_SIZE))?;
+    index.read_exact(&mut bytes)?;
+    Ok(Bucket::from_bytes(&bytes))
+}
+
+fn write_bucket(index: &File, position: u64, bucket: Bucket) -> io::Result<()> {
+    let mut index = index;
+    index.seek(SeekFrom::Start(position * BUCKET_SIZE))?;
+    index.write_all(&bucket.to_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, fs};
+
+    use crate::{
+        history::TransactionHistory,
+        model::{
+            id::{client_id::ClientId, transaction_id::TransactionId},
+            transaction::Transaction,
+            transaction_record::TransactionRecord,
+            transaction_type::TransactionType,
+        },
+        test_util::{temp_path, transaction},
+    };
+
+    use super::{slot, DiskHistory, INITIAL_CAPACITY};
+
+    fn deposit(tx: u32) -> TransactionRecord {
+        TransactionRecord::new(Transaction {
+            client: ClientId((tx % 7) as u16),
+            ..transaction(TransactionType::Deposit, tx, Some(tx))
+        })
+    }
+
+    #[test]
+    fn stores_and_grows() {
+        let directory = temp_path("history");
+        let mut history = DiskHistory::create(&directory).unwrap();
+        let count = INITIAL_CAPACITY as u32;
+
+        for tx in 0..count {
+            history.insert(&deposit(tx)).unwrap();
+        }
+        assert_eq!(history.len(), count as usize);
+        assert!(history.capacity > INITIAL_CAPACITY);
+
+        for tx in (0..count).step_by(97) {
+            let transaction = history.get(TransactionId(tx)).unwrap().unwrap().transaction;
+            assert_eq!(transaction.client, ClientId((tx % 7) as u16));
+            assert_eq!(transaction.amount, Some(tx.into()));
+        }
+        assert!(history.get(TransactionId(count)).unwrap().is_none());
+
+        let mut replacement = deposit(5);
+        replacement.transaction.amount = Some(500.into());
+        history.insert(&replacement).unwrap();
+        assert_eq!(history.len(), count as usize);
+        assert_eq!(
+            history
+                .get(TransactionId(5))
+                .unwrap()
+                .unwrap()
+                .transaction
+                .amount,
+            Some(500.into())
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn spreads_ids_that_differ_only_in_high_bits() {
+        let capacity = 64;
+        let slots: BTreeSet<u64> = (0..capacity as u32)
+            .map(|tx| slot(TransactionId(tx << 16), capacity))
+            .collect();
+        assert!(slots.iter().all(|&position| position < capacity));
+        assert!(slots.len() as u64 > capacity / 2);
+    }
+}
@@ -0,0 +1,43 @@
+use std::{collections::HashMap, io};
+
+use crate::model::{id::transaction_id::TransactionId, transaction_record::TransactionRecord};
+
+use super::TransactionHistory;
+
+/// Keeps every transaction in a [HashMap]
+///
+/// This is the fastest history, but memory usage grows with the number of transactions.
+#[derive(Debug, Default)]
+pub struct MemoryHistory {
+    transactions: HashMap<TransactionId, TransactionRecord>,
+}
+
+impl MemoryHistory {
+    pub fn new() -> MemoryHistory {
+        MemoryHistory::default()
+    }
+}
+
+impl TransactionHistory for MemoryHistory {
+    fn get(&self, tx: TransactionId) -> io::Result<Option<TransactionRecord>> {
+        Ok(self.transactions.get(&tx).cloned())
+    }
+
+    fn insert(&mut self, record: &TransactionRecord) -> io::Result<()> {
+        self.transactions
+            .insert(record.transaction.tx, record.clone());
+        Ok(())
+    }
+
+    fn records(&self) -> io::Result<Vec<TransactionRecord>> {
+        Ok(self.transactions.values().cloned().collect())
+    }
+
+    fn into_records(self: Box<Self>) -> io::Result<Vec<TransactionRecord>> {
+        Ok(self.transactions.into_values().collect())
+    }
+
+    fn len(&self) -> usize {
+        self.transactions.len()
+    }
+}
@@ -0,0 +1,39 @@
+//! Storage for processed transactions
+//!
+//! The [Store](crate::store::Store) keeps every deposit and withdrawal so that later
+//! disputes can find them, along with where each one is in the dispute lifecycle. How
+//! that history is kept is up to the [TransactionHistory] implementation, either
+//! entirely in memory or spilled to disk.
+
+pub mod disk_history;
+pub mod memory_history;
+
+use std::{fmt::Debug, io};
+
+use crate::model::{id::transaction_id::TransactionId, transaction_record::TransactionRecord};
+
+/// A keyed collection of processed transactions
+///
+/// Histories can be handed to other threads, see [pipeline](crate::pipeline).
+pub trait TransactionHistory: Debug + Send {
+    /// Looks up a previously inserted transaction
+    fn get(&self, tx: TransactionId) -> io::Result<Option<TransactionRecord>>;
+
+    /// Stores a transaction, replacing any existing record with the same id
+    fn insert(&mut self, record: &TransactionRecord) -> io::Result<()>;
+
+    /// Every stored transaction, in no particular order
+    fn records(&self) -> io::Result<Vec<TransactionRecord>>;
+
+    /// Every stored transaction, in no particular order, once the history is no longer needed
+    fn into_records(self: Box<Self>) -> io::Result<Vec<TransactionRecord>> {
+        self.records()
+    }
+
+    /// The number of stored transactions
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
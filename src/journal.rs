@@ -0,0 +1,126 @@
+//! An append-only journal of the transactions given to the [Store](crate::store::Store)
+//!
+//! Each transaction is written to the journal, and synced to disk, before any account is
+//! touched. If a run dies partway through, running it again with the same journal replays
+//! every journaled transaction and skips the input rows they came from, so that each row
+//! is applied exactly once.
+//!
+//! The journal is a file of json lines, one [JournalEntry] each. A last line cut short by a
+//! crash is dropped when the journal is opened.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::model::transaction::Transaction;
+
+/// A journaled transaction, and the input row it was read from
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JournalEntry {
+    /// Counts every journaled transaction, carried over between runs by snapshots
+    pub sequence: u64,
+    /// The input the row was read from
+    pub source: Option<String>,
+    /// The byte offset of the row within its input
+    pub offset: Option<u64>,
+    pub transaction: Transaction,
+}
+
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+}
+
+impl Journal {
+    /// Opens the journal at the given path, creating it if it doesn't exist
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Journal> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(Journal { file })
+    }
+
+    /// Reads every complete entry, dropping a last line left unfinished by a crash
+    pub fn entries(&mut self) -> io::Result<Vec<JournalEntry>> {
+        self.file.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(&self.file);
+        let mut entries = Vec::new();
+        let mut line = Vec::new();
+        let mut complete = 0;
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 || line.last() != Some(&b'\n') {
+                break;
+            }
+            entries.push(serde_json::from_slice(&line)?);
+            complete += line.len() as u64;
+        }
+        self.file.set_len(complete)?;
+        self.file.seek(SeekFrom::End(0))?;
+        Ok(entries)
+    }
+
+    /// Appends an entry, returning once it is synced to disk
+    pub fn append(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()
+    }
+
+    /// Empties the journal, once everything in it is no longer needed
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.sync_data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write};
+
+    use crate::{
+        model::transaction_type::TransactionType,
+        test_util::{temp_path, transaction},
+    };
+
+    use super::{Journal, JournalEntry};
+
+    fn entry(sequence: u64) -> JournalEntry {
+        JournalEntry {
+            sequence,
+            source: Some("input.csv".to_string()),
+            offset: Some(sequence * 10),
+            transaction: transaction(TransactionType::Deposit, sequence as u32, Some(1)),
+        }
+    }
+
+    #[test]
+    fn drops_an_unfinished_last_entry() {
+        let path = temp_path("journal-torn");
+        let mut journal = Journal::open(&path).unwrap();
+        journal.append(&entry(0)).unwrap();
+        journal.append(&entry(1)).unwrap();
+        write!(journal.file, "{{\"sequence\":2,\"sour").unwrap();
+
+        let mut journal = Journal::open(&path).unwrap();
+        let entries = journal.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].offset, Some(10));
+
+        journal.append(&entry(2)).unwrap();
+        assert_eq!(Journal::open(&path).unwrap().entries().unwrap().len(), 3);
+
+        journal.clear().unwrap();
+        assert!(Journal::open(&path).unwrap().entries().unwrap().is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+}
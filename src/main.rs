@@ -9,15 +9,136 @@
 //!
 //! ## Running
 //! ```bash
-//! cargo run input-file.csv
+//! cargo run -- process input-file.csv
 //! ```
 //!
 //! This will process the input file and output the final account balances
 //!
+//! Several inputs can be given, including glob patterns, and are processed in order into one
+//! set of balances. Use `-` to read from standard input:
+//! ```bash
+//! cargo run -- process monday.csv 'daily/*.csv'
+//! extract-job | cargo run -- process -
+//! ```
+//!
+//! Other subcommands are available, see `cargo run -- help` for all of their options:
+//! - `validate` checks that every row can be parsed, without applying any transactions
+//! - `replay` outputs the affected account's balances after every row
+//! - `inspect --client <id>` or `inspect --tx <id>` shows the details of a single client or transaction
+//!
+//! Results go to standard output, or to a file given with `-o`, as `csv` or `jsonl` (`--format`),
+//! with every balance printed to exactly `--precision` decimal places (4 by default, at most
+//! 12), rounded with `--rounding bankers|half-up|truncate`. `available + held` always equals the printed `total`.
+//!
+//! Accounts are listed by client id, or by balance with `--sort total|available|held`.
+//! Add `--reverse` for largest first and `--locked-only` to list only locked accounts.
+//!
+//! The exit code is `0` when every row was applied, `1` when some rows were rejected or
+//! could not be parsed, and `2` when processing could not finish.
+//!
+//! For inputs too large to keep every transaction in memory, pass a directory
+//! where the transaction history can be kept on disk instead:
+//! ```bash
+//! cargo run -- process input-file.csv --history-dir /tmp/pseudo-bank
+//! ```
+//!
+//! Built with the `sqlite` feature, accounts and transactions can instead be kept in a SQLite
+//! database, where they can be queried with SQL once processing is done. A later run given the
+//! same database carries on from the accounts and transactions already in it:
+//! ```bash
+//! cargo run --features sqlite -- process input-file.csv --database bank.db
+//! sqlite3 bank.db 'SELECT client, currency, available, held, total FROM balances'
+//! ```
+//!
+//! To process one day of transactions at a time, save the state of every account and transaction
+//! at the end of each run and carry on from it in the next:
+//! ```bash
+//! cargo run -- process monday.csv --save-snapshot bank.snapshot
+//! cargo run -- process tuesday.csv --load-snapshot bank.snapshot --save-snapshot bank.snapshot
+//! ```
+//! Snapshots are versioned and checksummed, a corrupt snapshot or one from an incompatible version
+//! is refused with exit code `2`.
+//!
+//! `--journal <path>` writes every transaction to a journal file, synced to disk, before applying
+//! it. If a run is interrupted, running it again with the same inputs and journal recovers what
+//! was journaled and carries on from the first row after it, so no row is applied twice. Rows
+//! refused the first time are reported again, and rows the loaded snapshot already covers are
+//! counted as skipped. The journal is emptied once the run finishes. It can't be combined with
+//! `--database`, whose state the journal doesn't cover.
+//!
+//! `--threads <n>` applies rows on `n` threads, keeping each client's rows on the same thread.
+//! Rows that involve clients on different threads, such as transfers between them, wait for the
+//! other threads to catch up, so the output is the same as with a single thread. It can't be
+//! combined with `--journal`, `--history-dir`, `--database` or `--dispute-expiry`.
+//!
+//! A service applying transactions from many threads at once can share a `ConcurrentStore`,
+//! which splits clients between locked shards the same way. Its transactions end up applied in a
+//! single order, the order of its journal if it has one, so replaying that journal on a `Store`
+//! gives the same accounts.
+//!
 //! Errors are output to standard error output.
 //! This application should run quickly when there aren't too many transactions that it
 //! needs to print due to error.
 //!
+//! Disputes hold the funds of a deposit until they are resolved or charged back. Disputing a
+//! withdrawal is refused unless `--withdrawal-disputes provisional-credit` is given, which holds
+//! the withdrawn amount as a credit that a chargeback makes available to the client.
+//! Disputing an unknown transaction, disputing one twice, or resolving or charging back one that
+//! isn't disputed are all reported as errors.
+//! A resolved transaction can be disputed again unless `--redisputes reject` is given, while a
+//! charged back one never can. `inspect --tx <id>` shows every state a transaction has been through.
+//!
+//! A `transfer` row moves `amount` from `client` to the client in a `destination` column. Either
+//! both accounts change or neither does, and transfers can't be disputed.
+//!
+//! Rows may have a three letter `currency` column, rows without one are in the base currency.
+//! Each account keeps a separate balance in every currency, and a dispute, resolve or chargeback
+//! naming a different currency from the original transaction is rejected. Only base currency
+//! balances are output unless `--by-currency` is given, which outputs one row per client and
+//! currency with a `currency` column.
+//!
+//! A `convert` row exchanges `amount` of the client's money in `currency` for the currency in a
+//! `to_currency` column, at the rate for that pair in a csv file with `from`, `to`, `rate` and
+//! `effective` columns, passed with `--rates rates.csv`. Rows with a `timestamp` use the rate in
+//! effect at that time, others the latest rate. The rate used is kept with the transaction, so
+//! disputing or charging back a conversion reverses exactly what it converted.
+//!
+//! Rows may have a `timestamp` column of RFC 3339 times, such as `2024-05-01T09:30:00Z`.
+//! Timestamped rows must be in time order, a row earlier than one already applied is rejected.
+//! `--reorder-window <rows>` holds back that many timestamped rows of each input and applies them
+//! earliest first, for inputs where rows arrive a little out of order.
+//! `inspect --client <id> --as-of <time>` shows the client's balances as they were at that time,
+//! which are only kept in memory when `--as-of` is given.
+//!
+//! With timestamps, `--dispute-window <days>` rejects disputes filed more than that many days
+//! after the transaction they dispute, and `--dispute-expiry <days>` resolves disputes still open
+//! that many days after they were filed. Expired disputes are resolved once a later timestamped
+//! row is applied, and each one is written to the audit log as a `resolve` with the reason
+//! `dispute expired`, timestamped at that row.
+//!
+//! Clients with a credit line can be given a base currency overdraft limit in a separate csv
+//! file with `client` and `overdraft_limit` columns, passed with `--client-settings settings.csv`.
+//! Their withdrawals and transfers may then take the available balance down to minus that limit,
+//! and the output gains `credit_limit` and `credit_used` columns.
+//!
+//! `fee` rows charge the client and may take the available balance up to `--fee-limit` below zero.
+//! `interest` rows credit the client and can't be disputed. The totals of each kind of transaction
+//! for every account can be written to a file with `--activity activity.csv`.
+//!
+//! Administrators can lock an account with a `freeze` row and lift any lock, including one from a
+//! chargeback, with an `unlock` row. Both need a `reason` column. Their `tx` only labels the row,
+//! it isn't checked for duplicates or kept, so audit entries are told apart by `client` and order
+//! rather than `tx`. Every lock and unlock can be written to a file with `--audit-log audit.csv`.
+//!
+//! Rows that can't be parsed, such as an unknown `type` or a malformed `amount`, are reported
+//! and skipped. Pass `--strict` to stop at the first one, once it is reported, with a fatal
+//! exit code instead.
+//!
+//! Errors can instead be written to a machine-readable report in `csv` or `jsonl` format:
+//! ```bash
+//! cargo run -- process input-file.csv --error-report errors.jsonl --error-format jsonl
+//! ```
+//!
 //! ### Example Input
 //! ```csv,no_run
 //! type,       client, tx, amount
@@ -35,29 +156,31 @@
 //! ### Example Output
 //! ```csv,no_run
 //! client,available,held,total,locked
-//! 1,0.5,0.0000,0.5,true
-//! 2,2,0.0000,2,false
+//! 1,0.5000,0.0000,0.5000,true
+//! 2,2.0000,0.0000,2.0000,false
 //! ```
 
-use std::env;
+use std::process;
 
-use csv::{ReaderBuilder, WriterBuilder};
-use spreadsheet::{read_spreadsheet, write_spreadsheet};
-use store::Store;
+use clap::Parser;
+use cli::Cli;
 
+pub mod cli;
+pub mod concurrent_store;
+pub mod history;
+pub mod journal;
 pub mod model;
+pub mod pipeline;
+pub mod report;
+pub mod snapshot;
 pub mod spreadsheet;
+pub mod storage;
 pub mod store;
+#[cfg(test)]
+pub mod test_util;
 
-/// Processes a given csv and outputs the final account data to std::io
+/// Runs the chosen subcommand and exits with its [Outcome](cli::Outcome)'s code
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let mut reader = ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_path(args[1].to_owned())
-        .unwrap();
-    let mut store = Store::new();
-    read_spreadsheet(&mut store, &mut reader);
-    let mut writer = WriterBuilder::new().from_writer(std::io::stdout());
-    write_spreadsheet(&mut store, &mut writer);
+    let outcome = cli::run(Cli::parse());
+    process::exit(outcome.code());
 }
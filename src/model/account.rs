@@ -1,61 +1,182 @@
 //! Holds a client's account information, such as current balance
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use rust_decimal::Decimal;
-use serde::{ser::SerializeStruct, Serialize, Serializer};
+use serde::{Deserialize, Serialize};
+
+use crate::history::TransactionHistory;
 
 use super::{
+    activity::Activity,
+    balances::Balances,
+    currency::Currency,
     error::transaction_error::TransactionError,
-    id::{client_id::ClientId, transaction_id::TransactionId},
+    id::client_id::ClientId,
+    ledger::Ledger,
+    policy::{account_policy::AccountPolicy, withdrawal_dispute_policy::WithdrawalDisputePolicy},
     transaction::Transaction,
     transaction_type::TransactionType,
 };
 
 /// Holds the data needed for a client's account
 ///
-/// Instead of having a separate field for held funds, this model has only a total balance and a list of current disputes.
-/// The available balance is determined by subtracting the disputed transactions from the current total balance.
+/// Instead of having a separate field for held funds, this model has only a settled balance and a list of current disputes.
+/// The available balance is determined by subtracting the disputed deposits from the settled balance.
+/// Disputed withdrawals and fees, when allowed by the [WithdrawalDisputePolicy], are provisionally
+/// credited back as held funds, so they add to the total without being available.
 /// This provides a layer of safety over just using calculations on a couple of numeric fields, namely that a dispute
 /// could be resolved multiple times, or a transaction disputed multiple times.
 ///
 /// With this method it _should_ be impossible to accidentally dispute twice or resolve twice.
 ///
-/// **Note:** There is currently no protection against deposits or withdrawals with the same transaction id.
-/// This would need to be implemented in the [Store](crate::store::Store).
+/// Deposits or withdrawals reusing a transaction id never reach the account,
+/// they are caught by the [Store](crate::store::Store) according to its
+/// [DuplicatePolicy](crate::model::policy::duplicate_policy::DuplicatePolicy).
+///
+/// Transfers are applied to the sending account like a withdrawal, and credited to the
+/// receiving account with [receive_transfer](Account::receive_transfer). They move money
+/// between two clients of the bank, so they can't be disputed by either of them.
+///
+/// Withdrawals and transfers may take the available balance below zero, down to the
+/// account's [overdraft limit](Account::set_overdraft_limit), which is zero unless the client
+/// has a credit line. Fees are charged like a withdrawal, except that they may go a further
+/// [AccountPolicy]'s `fee_limit` below that. Interest is credited like a deposit,
+/// but is paid by the bank, so it can't be disputed.
+///
+/// ## Currencies
+///
+/// Money in each currency is kept in its own [Ledger], and every transaction only touches
+/// the ledger of its own currency. The methods without a currency, such as
+/// [get_available](Account::get_available), are for the base currency.
+/// Overdraft and fee limits only apply to the base currency, balances in other currencies
+/// can't go below zero.
+///
+/// A conversion debits one ledger like a withdrawal and credits another at the rate recorded
+/// on the transaction. Disputing it holds the converted funds like a deposit in the currency
+/// they were converted to, and a chargeback undoes both sides at the recorded rate.
 ///
 /// ## Performance Considerations
 ///
 /// Calculating the available balance is fast as long as there aren't many disputes.
 /// This should be relatively rare, so the safety guarantees should be ultimately worth it.
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Account {
     id: ClientId,
-    balance: Decimal,
+    #[serde(with = "ledger_list")]
+    ledgers: BTreeMap<Option<Currency>, Ledger>,
     is_locked: bool,
     disputes: Vec<Transaction>,
+    overdraft_limit: Decimal,
 }
 
 impl Account {
     pub fn new(id: ClientId) -> Account {
         Account {
             id,
-            balance: 0.into(),
+            ledgers: BTreeMap::from([(None, Ledger::default())]),
             is_locked: false,
             disputes: Vec::new(),
+            overdraft_limit: 0.into(),
         }
     }
 
-    /// `total - disputed`
+    /// `settled - disputed deposits`, in the base currency
     pub fn get_available(&self) -> Decimal {
-        self.balance - self.get_held()
+        self.get_available_in(None)
     }
 
-    /// Sum of all disputed transactions
+    /// `available + held`, in the base currency
+    pub fn get_total(&self) -> Decimal {
+        self.get_total_in(None)
+    }
+
+    /// Sum of all disputed transactions in the base currency
     pub fn get_held(&self) -> Decimal {
+        self.get_held_in(None)
+    }
+
+    pub fn get_available_in(&self, currency: Option<&Currency>) -> Decimal {
+        self.get_balance_in(currency) - self.get_disputed(currency, true)
+    }
+
+    pub fn get_total_in(&self, currency: Option<&Currency>) -> Decimal {
+        self.get_balance_in(currency) + self.get_disputed(currency, false)
+    }
+
+    pub fn get_held_in(&self, currency: Option<&Currency>) -> Decimal {
+        self.get_disputed(currency, true) + self.get_disputed(currency, false)
+    }
+
+    /// Every balance in a currency at once
+    pub fn get_balances_in(&self, currency: Option<&Currency>) -> Balances {
+        Balances {
+            available: self.get_available_in(currency),
+            held: self.get_held_in(currency),
+            total: self.get_total_in(currency),
+            locked: self.is_locked,
+        }
+    }
+
+    /// The currencies this account has held money in, starting with the base currency
+    pub fn get_currencies(&self) -> impl Iterator<Item = Option<&Currency>> {
+        self.ledgers.keys().map(Option::as_ref)
+    }
+
+    fn get_balance_in(&self, currency: Option<&Currency>) -> Decimal {
+        self.ledgers
+            .get(&currency.copied())
+            .map_or(0.into(), |ledger| ledger.balance)
+    }
+
+    fn ledger_mut(&mut self, currency: Option<Currency>) -> &mut Ledger {
+        self.ledgers.entry(currency).or_default()
+    }
+
+    /// Sum of the disputed transactions in a currency that credited the account,
+    /// or of those that debited it
+    fn get_disputed(&self, currency: Option<&Currency>, credits: bool) -> Decimal {
         self.disputes
             .iter()
-            .fold(0.into(), |acc, t| acc + t.amount.unwrap_or(0.into()))
+            .filter_map(held_by)
+            .filter(|(held_currency, _, credit)| {
+                held_currency.as_ref() == currency && *credit == credits
+            })
+            .fold(0.into(), |acc, (_, amount, _)| acc + amount)
+    }
+
+    /// Credits the receiving side of a [Transfer](TransactionType::Transfer)
+    ///
+    /// The sending side goes through [apply_transaction](Account::apply_transaction) like a
+    /// withdrawal. The [Store](crate::store::Store) checks this account isn't locked
+    /// before either side is applied, so that a transfer is never half done.
+    pub fn receive_transfer(&mut self, transaction: &Transaction) {
+        let amount = transaction.amount.unwrap_or(0.into());
+        let ledger = self.ledger_mut(transaction.currency);
+        ledger.balance += amount;
+        ledger.activity.receive(amount);
+    }
+
+    /// Totals of the money that has moved through this account in the base currency
+    pub fn get_activity(&self) -> Activity {
+        self.get_activity_in(None)
+    }
+
+    pub fn get_activity_in(&self, currency: Option<&Currency>) -> Activity {
+        self.ledgers
+            .get(&currency.copied())
+            .map(|ledger| ledger.activity)
+            .unwrap_or_default()
+    }
+
+    /// How far below zero withdrawals and transfers may take the available balance
+    /// in the base currency
+    pub fn get_overdraft_limit(&self) -> Decimal {
+        self.overdraft_limit
+    }
+
+    pub fn set_overdraft_limit(&mut self, overdraft_limit: Decimal) {
+        self.overdraft_limit = overdraft_limit;
     }
 
     /// Whether or not new transactions can be applied to this account
@@ -67,154 +188,325 @@ impl Account {
         self.id
     }
 
+    /// The transactions currently under dispute
+    pub fn get_disputes(&self) -> &[Transaction] {
+        &self.disputes
+    }
+
     /// Alters the current balances using a given transaction.
     ///
     /// This is where the bulk of the processing in the application is done.
-    /// A clients account can be credited, depited, disputed, and locked through this method.
+    /// A clients account can be credited, depited, disputed, locked and unlocked through this method.
     ///
     /// Returns whether or not the transaction should be stored to the transaction list.
     ///
+    /// Amounts are expected to have been checked with
+    /// [validate_amount](Transaction::validate_amount) beforehand.
+    ///
     /// ```
     /// # let client_id = ClientId(0);
     /// # let transaction_id = TransactionId(0);
     /// # let mut account = Account::new(ClientId(1234));
-    /// # let transaction_store: HashMap<TransactionId, Transaction> = HashMap::new();
+    /// # let transaction_store = MemoryHistory::new();
+    /// let policy = AccountPolicy::default();
     /// let transaction = Transaction {
     ///     transaction_type: TransactionType::Deposit,
     ///     client: client_id,
     ///     tx: transaction_id,
     ///     amount: Some(10_000.into()),
+    ///     destination: None,
+    ///     currency: None,
+    ///     to_currency: None,
+    ///     rate: None,
+    ///     reason: None,
+    ///     timestamp: None,
     /// };
     ///
-    /// account.apply_transaction(transaction_store, transaction);
+    /// account.apply_transaction(&transaction_store, &transaction, policy);
     ///
     /// assert_eq!(account.get_available(), Decimal::from(10_000));
     /// ```
     pub fn apply_transaction(
         &mut self,
-        transaction_store: &HashMap<(ClientId, TransactionId), Transaction>,
+        transaction_store: &dyn TransactionHistory,
         transaction: &Transaction,
+        policy: &AccountPolicy,
     ) -> Result<bool, TransactionError> {
-        if self.is_locked {
+        if self.is_locked && transaction.transaction_type != TransactionType::Unlock {
             return Err(TransactionError::AccountLocked);
         }
         match transaction.transaction_type {
-            TransactionType::Deposit => {
-                self.balance += transaction.amount.unwrap_or(0.into());
+            TransactionType::Deposit | TransactionType::Interest => {
+                let amount = transaction.amount.ok_or(TransactionError::MissingAmount)?;
+                let ledger = self.ledger_mut(transaction.currency);
+                ledger.balance += amount;
+                ledger.activity.record(transaction.transaction_type, amount);
                 Ok(true)
             }
-            TransactionType::Withdraw => {
-                let amount = transaction.amount.unwrap_or(0.into());
-                let available = self.get_available();
-                if available - amount >= 0.into() {
-                    self.balance -= amount;
-                } else {
+            TransactionType::Withdraw | TransactionType::Transfer => {
+                let amount = transaction.amount.ok_or(TransactionError::MissingAmount)?;
+                let currency = transaction.currency.as_ref();
+                let available = self.get_available_in(currency);
+                let limit = self.limit_in(currency, 0.into());
+                if available - amount < -limit {
                     return Err(TransactionError::Overdraft {
                         available,
                         transaction_amount: amount,
+                        limit,
                     });
                 }
+                let ledger = self.ledger_mut(transaction.currency);
+                ledger.balance -= amount;
+                ledger.activity.record(transaction.transaction_type, amount);
+                Ok(true)
+            }
+            TransactionType::Fee => {
+                let amount = transaction.amount.ok_or(TransactionError::MissingAmount)?;
+                let currency = transaction.currency.as_ref();
+                let available = self.get_available_in(currency);
+                let limit = self.limit_in(currency, policy.fee_limit);
+                if available - amount < -limit {
+                    return Err(TransactionError::FeeLimit {
+                        available,
+                        fee: amount,
+                        limit,
+                    });
+                }
+                let ledger = self.ledger_mut(transaction.currency);
+                ledger.balance -= amount;
+                ledger.activity.record(transaction.transaction_type, amount);
+                Ok(true)
+            }
+            TransactionType::Convert => {
+                let amount = transaction.amount.ok_or(TransactionError::MissingAmount)?;
+                let converted = transaction
+                    .converted_amount()
+                    .ok_or(TransactionError::NoRate {
+                        from: transaction.currency,
+                        to: transaction.to_currency,
+                    })?;
+                let currency = transaction.currency.as_ref();
+                let available = self.get_available_in(currency);
+                let limit = self.limit_in(currency, 0.into());
+                if available - amount < -limit {
+                    return Err(TransactionError::Overdraft {
+                        available,
+                        transaction_amount: amount,
+                        limit,
+                    });
+                }
+                let ledger = self.ledger_mut(transaction.currency);
+                ledger.balance -= amount;
+                ledger.activity.record(transaction.transaction_type, amount);
+                let ledger = self.ledger_mut(transaction.to_currency);
+                ledger.balance += converted;
+                ledger.activity.receive_conversion(converted);
                 Ok(true)
             }
             TransactionType::Dispute => {
-                if let Some(disputed_transaction) =
-                    transaction_store.get(&(self.id, transaction.tx))
-                {
-                    if !self.disputes.iter().any(|t| t.tx == transaction.tx) {
-                        self.disputes.push(disputed_transaction.clone());
+                if self.disputes.iter().any(|t| t.tx == transaction.tx) {
+                    return Err(TransactionError::AlreadyDisputed { tx: transaction.tx });
+                }
+                let disputed_transaction = self.find_own(transaction_store, transaction)?;
+                check_currency(transaction, &disputed_transaction)?;
+                let disputable = match disputed_transaction.transaction_type {
+                    TransactionType::Deposit | TransactionType::Convert => true,
+                    TransactionType::Withdraw | TransactionType::Fee => {
+                        policy.withdrawal_disputes == WithdrawalDisputePolicy::ProvisionalCredit
                     }
+                    _ => false,
+                };
+                if !disputable {
+                    return Err(TransactionError::NotDisputable {
+                        tx: disputed_transaction.tx,
+                        transaction_type: disputed_transaction.transaction_type,
+                    });
                 }
+                self.disputes.push(disputed_transaction);
                 Ok(false)
             }
             TransactionType::Resolve => {
-                self.disputes = self
-                    .disputes
-                    .drain(..)
-                    .filter(|t| t.tx != transaction.tx)
-                    .collect::<Vec<_>>();
+                self.take_dispute(transaction_store, transaction)?;
                 Ok(false)
             }
             TransactionType::Chargeback => {
-                if let Some(disputed_transaction_amount) = self
-                    .disputes
-                    .iter()
-                    .find(|t| t.tx == transaction.tx)
-                    .and_then(|t| t.amount)
-                {
-                    self.is_locked = true;
-                    self.disputes = self
-                        .disputes
-                        .drain(..)
-                        .filter(|t| t.tx != transaction.tx)
-                        .collect::<Vec<_>>();
-                    self.balance -= disputed_transaction_amount;
-                } else {
-                    return Err(TransactionError::TransactionNotDisputed);
+                let disputed_transaction = self.take_dispute(transaction_store, transaction)?;
+                // Charging back a deposit takes the funds away, while charging back
+                // a withdrawal or fee gives them back to the client. Charging back a conversion
+                // takes away what it credited and gives back what it debited, at the same rate.
+                if let Some((currency, amount, credit)) = held_by(&disputed_transaction) {
+                    let ledger = self.ledger_mut(currency);
+                    if credit {
+                        ledger.balance -= amount;
+                    } else {
+                        ledger.balance += amount;
+                    }
+                }
+                if disputed_transaction.transaction_type == TransactionType::Convert {
+                    let amount = disputed_transaction.amount.unwrap_or(0.into());
+                    self.ledger_mut(disputed_transaction.currency).balance += amount;
+                }
+                self.is_locked = true;
+                Ok(false)
+            }
+            TransactionType::Freeze => {
+                self.is_locked = true;
+                Ok(false)
+            }
+            TransactionType::Unlock => {
+                if !self.is_locked {
+                    return Err(TransactionError::AccountNotLocked);
                 }
+                self.is_locked = false;
                 Ok(false)
             }
         }
     }
-}
 
-impl Serialize for Account {
-    /// Serializes the account data into a human readable set of fields.
+    /// How far below zero a debit may take the available balance in a currency
     ///
-    /// This does not represent the underlying data in the account.
-    /// It is only intended for outputing a summary.
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut state = serializer.serialize_struct("Account", 4)?;
-        state.serialize_field("client", &self.id)?;
-        state.serialize_field("available", &self.get_available().round_dp(4))?;
-        state.serialize_field("held", &self.get_held().round_dp(4))?;
-        state.serialize_field("total", &self.balance.round_dp(4))?;
-        state.serialize_field("locked", &self.is_locked)?;
-        state.end()
+    /// Only the base currency has an overdraft limit, and only the base currency gets the
+    /// extra allowance given for fees.
+    fn limit_in(&self, currency: Option<&Currency>, allowance: Decimal) -> Decimal {
+        match currency {
+            None => self.overdraft_limit + allowance,
+            Some(_) => 0.into(),
+        }
+    }
+
+    /// Looks up the transaction that a dispute, resolve or chargeback refers to
+    ///
+    /// Transactions belonging to other clients are treated as if they don't exist.
+    fn find_own(
+        &self,
+        transaction_store: &dyn TransactionHistory,
+        transaction: &Transaction,
+    ) -> Result<Transaction, TransactionError> {
+        transaction_store
+            .get(transaction.tx)?
+            .map(|record| record.transaction)
+            .filter(|t| t.client == self.id)
+            .ok_or(TransactionError::TransactionDoesNotExist { tx: transaction.tx })
+    }
+
+    /// Removes and returns the dispute that a resolve or chargeback refers to
+    fn take_dispute(
+        &mut self,
+        transaction_store: &dyn TransactionHistory,
+        transaction: &Transaction,
+    ) -> Result<Transaction, TransactionError> {
+        match self.disputes.iter().position(|t| t.tx == transaction.tx) {
+            Some(index) => {
+                check_currency(transaction, &self.disputes[index])?;
+                Ok(self.disputes.remove(index))
+            }
+            None => {
+                self.find_own(transaction_store, transaction)?;
+                Err(TransactionError::NotDisputed { tx: transaction.tx })
+            }
+        }
+    }
+}
+
+/// The currency and amount a disputed transaction holds, and whether it credited the account
+///
+/// A disputed conversion holds what it credited, in the currency it converted to.
+fn held_by(transaction: &Transaction) -> Option<(Option<Currency>, Decimal, bool)> {
+    match transaction.transaction_type {
+        TransactionType::Deposit => Some((transaction.currency, transaction.amount?, true)),
+        TransactionType::Convert => Some((
+            transaction.to_currency,
+            transaction.converted_amount()?,
+            true,
+        )),
+        TransactionType::Withdraw | TransactionType::Fee => {
+            Some((transaction.currency, transaction.amount?, false))
+        }
+        _ => None,
+    }
+}
+
+/// Refuses a dispute, resolve or chargeback naming a different currency from the transaction
+/// it refers to
+///
+/// Rows that leave the currency out are taken to mean the original's currency.
+fn check_currency(
+    transaction: &Transaction,
+    original: &Transaction,
+) -> Result<(), TransactionError> {
+    match transaction.currency {
+        Some(found) if transaction.currency != original.currency => {
+            Err(TransactionError::CurrencyMismatch {
+                tx: original.tx,
+                expected: original.currency,
+                found,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Writes ledgers as a list of `(currency, ledger)` pairs, as the base currency can't be a
+/// json object key
+mod ledger_list {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::model::{currency::Currency, ledger::Ledger};
+
+    pub fn serialize<S: Serializer>(
+        ledgers: &BTreeMap<Option<Currency>, Ledger>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        ledgers.iter().collect::<Vec<_>>().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<Option<Currency>, Ledger>, D::Error> {
+        let ledgers = Vec::<(Option<Currency>, Ledger)>::deserialize(deserializer)?;
+        Ok(ledgers.into_iter().collect())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
-
     use rust_decimal::Decimal;
 
-    use crate::model::{
-        id::{client_id::ClientId, transaction_id::TransactionId},
-        transaction::Transaction,
-        transaction_type::TransactionType,
+    use crate::{
+        history::{memory_history::MemoryHistory, TransactionHistory},
+        model::{
+            error::transaction_error::TransactionError,
+            id::{client_id::ClientId, transaction_id::TransactionId},
+            policy::{
+                account_policy::AccountPolicy, withdrawal_dispute_policy::WithdrawalDisputePolicy,
+            },
+            transaction::Transaction,
+            transaction_type::TransactionType,
+        },
+        test_util::transaction,
     };
 
     use super::Account;
 
-    fn setup() -> (Account, HashMap<(ClientId, TransactionId), Transaction>) {
-        (Account::new(ClientId(1)), HashMap::new())
+    fn setup() -> (Account, MemoryHistory) {
+        (Account::new(ClientId(1)), MemoryHistory::new())
     }
 
     #[test]
     fn test_deposit_withdraw() {
         let (mut account, transaction_store) = setup();
 
-        let deposit = Transaction {
-            transaction_type: TransactionType::Deposit,
-            client: account.get_id(),
-            tx: TransactionId(1),
-            amount: Some(10.into()),
-        };
-        let result = account.apply_transaction(&transaction_store, &deposit);
+        let deposit = transaction(TransactionType::Deposit, 1, Some(10));
+        let result =
+            account.apply_transaction(&transaction_store, &deposit, &AccountPolicy::default());
         assert!(result.is_ok());
         assert_eq!(account.get_available(), Decimal::from(10));
 
-        let withdraw = Transaction {
-            transaction_type: TransactionType::Withdraw,
-            client: account.get_id(),
-            tx: TransactionId(2),
-            amount: Some(5.into()),
-        };
-        let result = account.apply_transaction(&transaction_store, &withdraw);
+        let withdraw = transaction(TransactionType::Withdraw, 2, Some(5));
+        let result =
+            account.apply_transaction(&transaction_store, &withdraw, &AccountPolicy::default());
         assert!(result.is_ok());
         assert_eq!(account.get_available(), Decimal::from(5));
     }
@@ -223,36 +515,24 @@ mod tests {
     fn test_dispute_resolve() {
         let (mut account, mut transaction_store) = setup();
 
-        let deposit = Transaction {
-            transaction_type: TransactionType::Deposit,
-            client: account.get_id(),
-            tx: TransactionId(1),
-            amount: Some(10.into()),
-        };
-        let result = account.apply_transaction(&transaction_store, &deposit);
+        let deposit = transaction(TransactionType::Deposit, 1, Some(10));
+        let result =
+            account.apply_transaction(&transaction_store, &deposit, &AccountPolicy::default());
         assert!(result.is_ok());
         assert_eq!(account.get_available(), Decimal::from(10));
 
-        transaction_store.insert((account.get_id(), deposit.tx), deposit);
+        transaction_store.insert(&deposit.clone().into()).unwrap();
 
-        let dispute = Transaction {
-            transaction_type: TransactionType::Dispute,
-            client: account.get_id(),
-            tx: TransactionId(1),
-            amount: None,
-        };
-        let result = account.apply_transaction(&transaction_store, &dispute);
+        let dispute = transaction(TransactionType::Dispute, 1, None);
+        let result =
+            account.apply_transaction(&transaction_store, &dispute, &AccountPolicy::default());
         assert!(result.is_ok());
         assert_eq!(account.get_available(), Decimal::from(0));
         assert_eq!(account.get_held(), Decimal::from(10));
 
-        let resolve = Transaction {
-            transaction_type: TransactionType::Resolve,
-            client: account.get_id(),
-            tx: TransactionId(1),
-            amount: None,
-        };
-        let result = account.apply_transaction(&transaction_store, &resolve);
+        let resolve = transaction(TransactionType::Resolve, 1, None);
+        let result =
+            account.apply_transaction(&transaction_store, &resolve, &AccountPolicy::default());
         assert!(result.is_ok());
         assert_eq!(account.get_available(), Decimal::from(10));
         assert_eq!(account.get_held(), Decimal::from(0));
@@ -262,51 +542,290 @@ mod tests {
     fn test_dispute_chargeback() {
         let (mut account, mut transaction_store) = setup();
 
-        let deposit = Transaction {
-            transaction_type: TransactionType::Deposit,
-            client: account.get_id(),
-            tx: TransactionId(1),
-            amount: Some(10.into()),
-        };
-        let result = account.apply_transaction(&transaction_store, &deposit);
+        let deposit = transaction(TransactionType::Deposit, 1, Some(10));
+        let result =
+            account.apply_transaction(&transaction_store, &deposit, &AccountPolicy::default());
         assert!(result.is_ok());
         assert_eq!(account.get_available(), Decimal::from(10));
 
-        transaction_store.insert((account.get_id(), deposit.tx), deposit);
+        transaction_store.insert(&deposit.clone().into()).unwrap();
 
-        let dispute = Transaction {
-            transaction_type: TransactionType::Dispute,
-            client: account.get_id(),
-            tx: TransactionId(1),
-            amount: None,
-        };
-        let result = account.apply_transaction(&transaction_store, &dispute);
+        let dispute = transaction(TransactionType::Dispute, 1, None);
+        let result =
+            account.apply_transaction(&transaction_store, &dispute, &AccountPolicy::default());
         assert!(result.is_ok());
         assert_eq!(account.get_available(), Decimal::from(0));
         assert_eq!(account.get_held(), Decimal::from(10));
 
-        let chargeback = Transaction {
-            transaction_type: TransactionType::Chargeback,
-            client: account.get_id(),
-            tx: TransactionId(1),
-            amount: None,
-        };
-        let result = account.apply_transaction(&transaction_store, &chargeback);
+        let chargeback = transaction(TransactionType::Chargeback, 1, None);
+        let result =
+            account.apply_transaction(&transaction_store, &chargeback, &AccountPolicy::default());
         assert!(result.is_ok());
         assert!(account.is_locked());
         assert_eq!(account.get_available(), Decimal::from(0));
         assert_eq!(account.get_held(), Decimal::from(0));
 
-        let deposit = Transaction {
-            transaction_type: TransactionType::Resolve,
-            client: account.get_id(),
-            tx: TransactionId(1),
-            amount: None,
-        };
-        let result = account.apply_transaction(&transaction_store, &deposit);
+        let deposit = transaction(TransactionType::Resolve, 1, None);
+        let result =
+            account.apply_transaction(&transaction_store, &deposit, &AccountPolicy::default());
         assert!(result.is_err());
         assert!(account.is_locked());
         assert_eq!(account.get_available(), Decimal::from(0));
         assert_eq!(account.get_held(), Decimal::from(0));
     }
+
+    #[test]
+    fn test_dispute_errors() {
+        let (mut account, mut transaction_store) = setup();
+        let policy = &AccountPolicy::default();
+        let reference = |transaction_type, tx| transaction(transaction_type, tx, None);
+        let dispute = reference(TransactionType::Dispute, 1);
+        let resolve = reference(TransactionType::Resolve, 1);
+        let chargeback = reference(TransactionType::Chargeback, 1);
+        let unknown = reference(TransactionType::Dispute, 2);
+
+        let deposit = transaction(TransactionType::Deposit, 1, Some(10));
+        account
+            .apply_transaction(&transaction_store, &deposit, policy)
+            .unwrap();
+        transaction_store.insert(&deposit.clone().into()).unwrap();
+
+        assert!(matches!(
+            account.apply_transaction(&transaction_store, &unknown, policy),
+            Err(TransactionError::TransactionDoesNotExist {
+                tx: TransactionId(2)
+            })
+        ));
+        assert!(matches!(
+            account.apply_transaction(&transaction_store, &resolve, policy),
+            Err(TransactionError::NotDisputed {
+                tx: TransactionId(1)
+            })
+        ));
+        assert!(matches!(
+            account.apply_transaction(&transaction_store, &chargeback, policy),
+            Err(TransactionError::NotDisputed {
+                tx: TransactionId(1)
+            })
+        ));
+
+        account
+            .apply_transaction(&transaction_store, &dispute, policy)
+            .unwrap();
+        assert!(matches!(
+            account.apply_transaction(&transaction_store, &dispute, policy),
+            Err(TransactionError::AlreadyDisputed {
+                tx: TransactionId(1)
+            })
+        ));
+        assert_eq!(account.get_held(), Decimal::from(10));
+
+        let mut other = Account::new(ClientId(2));
+        assert!(matches!(
+            other.apply_transaction(&transaction_store, &resolve, policy),
+            Err(TransactionError::TransactionDoesNotExist {
+                tx: TransactionId(1)
+            })
+        ));
+    }
+
+    /// Deposits 10, withdraws 4 and disputes the withdrawal
+    fn dispute_withdrawal(
+        policy: &AccountPolicy,
+    ) -> (Account, MemoryHistory, Result<bool, TransactionError>) {
+        let (mut account, mut transaction_store) = setup();
+        for (tx, transaction_type, amount) in [
+            (1, TransactionType::Deposit, 10),
+            (2, TransactionType::Withdraw, 4),
+        ] {
+            let transaction = transaction(transaction_type, tx, Some(amount));
+            assert!(account
+                .apply_transaction(&transaction_store, &transaction, policy)
+                .is_ok());
+            transaction_store
+                .insert(&transaction.clone().into())
+                .unwrap();
+        }
+
+        let dispute = transaction(TransactionType::Dispute, 2, None);
+        let result = account.apply_transaction(&transaction_store, &dispute, policy);
+        (account, transaction_store, result)
+    }
+
+    #[test]
+    fn test_dispute_withdrawal_rejected() {
+        let (account, _, result) = dispute_withdrawal(&AccountPolicy::default());
+        assert!(matches!(
+            result,
+            Err(TransactionError::NotDisputable {
+                transaction_type: TransactionType::Withdraw,
+                ..
+            })
+        ));
+        assert_eq!(account.get_available(), Decimal::from(6));
+        assert_eq!(account.get_held(), Decimal::from(0));
+    }
+
+    #[test]
+    fn test_dispute_withdrawal_resolve() {
+        let policy = &AccountPolicy {
+            withdrawal_disputes: WithdrawalDisputePolicy::ProvisionalCredit,
+            ..AccountPolicy::default()
+        };
+        let (mut account, transaction_store, result) = dispute_withdrawal(policy);
+        assert!(result.is_ok());
+        assert_eq!(account.get_available(), Decimal::from(6));
+        assert_eq!(account.get_held(), Decimal::from(4));
+        assert_eq!(account.get_total(), Decimal::from(10));
+
+        let resolve = transaction(TransactionType::Resolve, 2, None);
+        assert!(account
+            .apply_transaction(&transaction_store, &resolve, policy)
+            .is_ok());
+        assert_eq!(account.get_available(), Decimal::from(6));
+        assert_eq!(account.get_held(), Decimal::from(0));
+        assert_eq!(account.get_total(), Decimal::from(6));
+    }
+
+    #[test]
+    fn test_dispute_withdrawal_chargeback() {
+        let policy = &AccountPolicy {
+            withdrawal_disputes: WithdrawalDisputePolicy::ProvisionalCredit,
+            ..AccountPolicy::default()
+        };
+        let (mut account, transaction_store, result) = dispute_withdrawal(policy);
+        assert!(result.is_ok());
+
+        let chargeback = transaction(TransactionType::Chargeback, 2, None);
+        assert!(account
+            .apply_transaction(&transaction_store, &chargeback, policy)
+            .is_ok());
+        assert!(account.is_locked());
+        assert_eq!(account.get_available(), Decimal::from(10));
+        assert_eq!(account.get_held(), Decimal::from(0));
+        assert_eq!(account.get_total(), Decimal::from(10));
+    }
+
+    fn apply(
+        account: &mut Account,
+        transaction_store: &mut MemoryHistory,
+        transaction_type: TransactionType,
+        tx: u32,
+        amount: u32,
+        policy: &AccountPolicy,
+    ) -> Result<bool, TransactionError> {
+        let transaction = transaction(transaction_type, tx, Some(amount));
+        let result = account.apply_transaction(transaction_store, &transaction, policy);
+        transaction_store.insert(&transaction.into()).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_fee_limit() {
+        let (mut account, mut transaction_store) = setup();
+        let policy = &AccountPolicy {
+            fee_limit: 5.into(),
+            ..AccountPolicy::default()
+        };
+        let mut apply = |transaction_type, tx, amount| {
+            apply(
+                &mut account,
+                &mut transaction_store,
+                transaction_type,
+                tx,
+                amount,
+                policy,
+            )
+        };
+
+        assert!(apply(TransactionType::Deposit, 1, 2).is_ok());
+        assert!(apply(TransactionType::Fee, 2, 6).is_ok());
+        assert!(matches!(
+            apply(TransactionType::Fee, 3, 2),
+            Err(TransactionError::FeeLimit { .. })
+        ));
+        assert!(matches!(
+            apply(TransactionType::Withdraw, 4, 1),
+            Err(TransactionError::Overdraft { .. })
+        ));
+        assert_eq!(account.get_available(), Decimal::from(-4));
+        assert_eq!(account.get_activity().fees, Decimal::from(6));
+    }
+
+    #[test]
+    fn test_interest_not_disputable() {
+        let (mut account, mut transaction_store) = setup();
+        let policy = &AccountPolicy::default();
+        assert!(apply(
+            &mut account,
+            &mut transaction_store,
+            TransactionType::Interest,
+            1,
+            3,
+            policy
+        )
+        .is_ok());
+
+        let dispute = transaction(TransactionType::Dispute, 1, None);
+        assert!(matches!(
+            account.apply_transaction(&transaction_store, &dispute, policy),
+            Err(TransactionError::NotDisputable {
+                transaction_type: TransactionType::Interest,
+                ..
+            })
+        ));
+        assert_eq!(account.get_available(), Decimal::from(3));
+        assert_eq!(account.get_activity().interest, Decimal::from(3));
+    }
+
+    #[test]
+    fn test_currencies() {
+        let (mut account, mut transaction_store) = setup();
+        let policy = &AccountPolicy::default();
+        let eur = "EUR".parse().unwrap();
+        let transaction = |transaction_type, tx, amount: Option<u32>, currency| Transaction {
+            currency,
+            ..transaction(transaction_type, tx, amount)
+        };
+
+        for deposit in [
+            transaction(TransactionType::Deposit, 1, Some(10), None),
+            transaction(TransactionType::Deposit, 2, Some(3), Some(eur)),
+        ] {
+            assert!(account
+                .apply_transaction(&transaction_store, &deposit, policy)
+                .is_ok());
+            transaction_store.insert(&deposit.into()).unwrap();
+        }
+
+        let withdraw = transaction(TransactionType::Withdraw, 3, Some(5), Some(eur));
+        assert!(matches!(
+            account.apply_transaction(&transaction_store, &withdraw, policy),
+            Err(TransactionError::Overdraft { .. })
+        ));
+
+        let dispute = transaction(
+            TransactionType::Dispute,
+            2,
+            None,
+            Some("GBP".parse().unwrap()),
+        );
+        assert!(matches!(
+            account.apply_transaction(&transaction_store, &dispute, policy),
+            Err(TransactionError::CurrencyMismatch { .. })
+        ));
+        let dispute = transaction(TransactionType::Dispute, 2, None, None);
+        assert!(account
+            .apply_transaction(&transaction_store, &dispute, policy)
+            .is_ok());
+
+        assert_eq!(account.get_available(), Decimal::from(10));
+        assert_eq!(account.get_held(), Decimal::from(0));
+        assert_eq!(account.get_available_in(Some(&eur)), Decimal::from(0));
+        assert_eq!(account.get_held_in(Some(&eur)), Decimal::from(3));
+        assert_eq!(
+            account.get_currencies().collect::<Vec<_>>(),
+            vec![None, Some(&eur)]
+        );
+    }
 }
@@ -0,0 +1,126 @@
+//! The human readable view of an [Account] used for output
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::{account::Account, decimal_format::DecimalFormat, id::client_id::ClientId};
+
+/// A summary of an account's balances, rounded for output
+///
+/// This does not represent the underlying data in the account.
+/// It is only intended for outputing a summary.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct AccountSummary {
+    pub client: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    /// The client's overdraft limit, only output when client settings were loaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credit_limit: Option<Decimal>,
+    /// How much of the overdraft limit is in use, only output when client settings were loaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credit_used: Option<Decimal>,
+}
+
+impl AccountSummary {
+    /// Formats the balances consistently with each other
+    ///
+    /// `held` and `total` are formatted, and `available` is taken as their difference,
+    /// so that `available + held == total` holds exactly for the printed numbers.
+    pub fn new(account: &Account, format: &DecimalFormat) -> AccountSummary {
+        let held = format.apply(account.get_held());
+        let total = format.apply(account.get_total());
+        AccountSummary {
+            client: account.get_id(),
+            available: total - held,
+            held,
+            total,
+            locked: account.is_locked(),
+            credit_limit: None,
+            credit_used: None,
+        }
+    }
+
+    /// Adds the `credit_limit` and `credit_used` columns
+    ///
+    /// Either every row of an output should have them or none should, so this is decided
+    /// for the whole output rather than by whether this account has a credit line.
+    pub fn with_credit(mut self, account: &Account, format: &DecimalFormat) -> AccountSummary {
+        let zero = format.apply(0.into());
+        self.credit_limit = Some(format.apply(account.get_overdraft_limit()));
+        self.credit_used = Some(if self.available < zero {
+            -self.available
+        } else {
+            zero
+        });
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        history::{memory_history::MemoryHistory, TransactionHistory},
+        model::{
+            account::Account,
+            decimal_format::{DecimalFormat, Rounding},
+            id::{client_id::ClientId, transaction_id::TransactionId},
+            policy::account_policy::AccountPolicy,
+            transaction::Transaction,
+            transaction_type::TransactionType,
+        },
+    };
+
+    use super::AccountSummary;
+
+    #[test]
+    fn printed_balances_add_up() {
+        let mut account = Account::new(ClientId(1));
+        let mut history = MemoryHistory::new();
+        for (tx, amount) in [(1, "0.00009"), (2, "0.00009")] {
+            let deposit = Transaction {
+                transaction_type: TransactionType::Deposit,
+                client: ClientId(1),
+                tx: TransactionId(tx),
+                amount: Some(amount.parse().unwrap()),
+                destination: None,
+                currency: None,
+                to_currency: None,
+                rate: None,
+                reason: None,
+                timestamp: None,
+            };
+            account
+                .apply_transaction(&history, &deposit, &AccountPolicy::default())
+                .unwrap();
+            history.insert(&deposit.clone().into()).unwrap();
+        }
+        let dispute = Transaction {
+            transaction_type: TransactionType::Dispute,
+            client: ClientId(1),
+            tx: TransactionId(1),
+            amount: None,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            reason: None,
+            timestamp: None,
+        };
+        account
+            .apply_transaction(&history, &dispute, &AccountPolicy::default())
+            .unwrap();
+
+        let format = DecimalFormat {
+            scale: 4,
+            rounding: Rounding::Truncate,
+        };
+        let summary = AccountSummary::new(&account, &format);
+        assert_eq!(summary.total.to_string(), "0.0001");
+        assert_eq!(summary.held.to_string(), "0.0000");
+        assert_eq!(summary.available.to_string(), "0.0001");
+        assert_eq!(summary.available + summary.held, summary.total);
+    }
+}
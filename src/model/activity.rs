@@ -0,0 +1,51 @@
+//! Running totals of what has moved through an account
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use super::transaction_type::TransactionType;
+
+/// The sum of every applied transaction of each kind that moves money
+///
+/// Disputes, resolves and chargebacks aren't counted, they only move money between the
+/// available and held balances or take back an earlier transaction.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Activity {
+    pub deposits: Decimal,
+    pub withdrawals: Decimal,
+    pub transfers_in: Decimal,
+    pub transfers_out: Decimal,
+    pub fees: Decimal,
+    pub interest: Decimal,
+    pub conversions_in: Decimal,
+    pub conversions_out: Decimal,
+}
+
+impl Activity {
+    /// Counts an applied transaction of the given type
+    ///
+    /// Transfers are counted as sent, see [receive](Activity::receive) for the other side,
+    /// and so are conversions, see [receive_conversion](Activity::receive_conversion).
+    pub fn record(&mut self, transaction_type: TransactionType, amount: Decimal) {
+        let total = match transaction_type {
+            TransactionType::Deposit => &mut self.deposits,
+            TransactionType::Withdraw => &mut self.withdrawals,
+            TransactionType::Transfer => &mut self.transfers_out,
+            TransactionType::Fee => &mut self.fees,
+            TransactionType::Interest => &mut self.interest,
+            TransactionType::Convert => &mut self.conversions_out,
+            _ => return,
+        };
+        *total += amount;
+    }
+
+    /// Counts the receiving side of a transfer
+    pub fn receive(&mut self, amount: Decimal) {
+        self.transfers_in += amount;
+    }
+
+    /// Counts the currency a conversion was made into
+    pub fn receive_conversion(&mut self, amount: Decimal) {
+        self.conversions_in += amount;
+    }
+}
@@ -0,0 +1,37 @@
+//! The human readable view of an [Account]'s [Activity](super::activity::Activity)
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::{account::Account, decimal_format::DecimalFormat, id::client_id::ClientId};
+
+/// The totals of each kind of transaction applied to an account, rounded for output
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct ActivitySummary {
+    pub client: ClientId,
+    pub deposits: Decimal,
+    pub withdrawals: Decimal,
+    pub transfers_in: Decimal,
+    pub transfers_out: Decimal,
+    pub fees: Decimal,
+    pub interest: Decimal,
+    pub conversions_in: Decimal,
+    pub conversions_out: Decimal,
+}
+
+impl ActivitySummary {
+    pub fn new(account: &Account, format: &DecimalFormat) -> ActivitySummary {
+        let activity = account.get_activity();
+        ActivitySummary {
+            client: account.get_id(),
+            deposits: format.apply(activity.deposits),
+            withdrawals: format.apply(activity.withdrawals),
+            transfers_in: format.apply(activity.transfers_in),
+            transfers_out: format.apply(activity.transfers_out),
+            fees: format.apply(activity.fees),
+            interest: format.apply(activity.interest),
+            conversions_in: format.apply(activity.conversions_in),
+            conversions_out: format.apply(activity.conversions_out),
+        }
+    }
+}
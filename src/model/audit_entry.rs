@@ -0,0 +1,24 @@
+//! A record of an account being locked or unlocked, or of a dispute expiring
+
+use serde::Serialize;
+
+use super::{
+    id::{client_id::ClientId, transaction_id::TransactionId},
+    transaction_type::TransactionType,
+};
+
+/// A change to whether an account is locked, and the transaction that caused it
+///
+/// Locks come from chargebacks and freezes, and are lifted by unlocks.
+/// A dispute left open past its deadline is also recorded, as the `resolve` that closed it.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub client: ClientId,
+    /// The id of the row that made the change, which needn't be unique for freezes and unlocks
+    pub tx: TransactionId,
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+    /// Whether the account is locked after the change
+    pub locked: bool,
+    pub reason: Option<String>,
+}
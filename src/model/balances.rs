@@ -0,0 +1,12 @@
+//! An account's balances in one currency at one point in time
+
+use rust_decimal::Decimal;
+
+/// The balances of an [Account](super::account::Account) in a single currency, unrounded
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Balances {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
@@ -0,0 +1,31 @@
+//! Per-client configuration, loaded separately from the transactions
+
+use rust_decimal::Decimal;
+use serde::{de, Deserialize, Deserializer};
+
+use super::id::client_id::ClientId;
+
+/// One row of the client settings file
+///
+/// ```csv,no_run
+/// client, overdraft_limit
+/// 1,      500.00
+/// ```
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct ClientSettings {
+    pub client: ClientId,
+    /// How far below zero withdrawals and transfers may take the available balance
+    #[serde(deserialize_with = "non_negative")]
+    pub overdraft_limit: Decimal,
+}
+
+fn non_negative<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+    let limit = <Decimal as Deserialize>::deserialize(deserializer)?;
+    if limit < Decimal::from(0) {
+        return Err(de::Error::custom(format!(
+            "overdraft limit {} is below zero",
+            limit
+        )));
+    }
+    Ok(limit)
+}
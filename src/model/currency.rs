@@ -0,0 +1,75 @@
+//! Currency codes
+
+use std::{convert::TryFrom, fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// A three letter currency code, such as `EUR`
+///
+/// Transactions without a currency are in the base currency, which is left unnamed.
+/// It is represented by [None] wherever a currency is optional.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub fn as_str(&self) -> &str {
+        // Only ever built from ascii letters, see the FromStr implementation
+        std::str::from_utf8(&self.0).unwrap_or_default()
+    }
+
+    /// The code of an optional currency, or `base` for the base currency
+    pub fn name(currency: Option<&Currency>) -> &str {
+        currency.map_or("base", Currency::as_str)
+    }
+}
+
+impl FromStr for Currency {
+    type Err = String;
+
+    /// Parses a code of three ascii letters, in either case
+    fn from_str(code: &str) -> Result<Currency, String> {
+        match code.as_bytes() {
+            &[a, b, c] if code.bytes().all(|byte| byte.is_ascii_alphabetic()) => Ok(Currency([
+                a.to_ascii_uppercase(),
+                b.to_ascii_uppercase(),
+                c.to_ascii_uppercase(),
+            ])),
+            _ => Err(format!("{:?} is not a three letter currency code", code)),
+        }
+    }
+}
+
+impl TryFrom<String> for Currency {
+    type Error = String;
+
+    fn try_from(code: String) -> Result<Currency, String> {
+        code.parse()
+    }
+}
+
+impl From<Currency> for String {
+    fn from(currency: Currency) -> String {
+        currency.as_str().to_string()
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Currency;
+
+    #[test]
+    fn parses_three_letter_codes() {
+        let currency: Currency = "eur".parse().unwrap();
+        assert_eq!(currency.to_string(), "EUR");
+        assert!("EURO".parse::<Currency>().is_err());
+        assert!("E1R".parse::<Currency>().is_err());
+        assert!("".parse::<Currency>().is_err());
+    }
+}
@@ -0,0 +1,41 @@
+//! The human readable view of an [Account]'s balances in one currency
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::{
+    account::Account, currency::Currency, decimal_format::DecimalFormat, id::client_id::ClientId,
+};
+
+/// A summary of an account's balances in a single currency, rounded for output
+///
+/// Like an [AccountSummary](super::account_summary::AccountSummary), `available + held == total`
+/// holds exactly for the printed numbers. The base currency is written as an empty `currency`.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct CurrencySummary {
+    pub client: ClientId,
+    pub currency: Option<Currency>,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl CurrencySummary {
+    pub fn new(
+        account: &Account,
+        currency: Option<&Currency>,
+        format: &DecimalFormat,
+    ) -> CurrencySummary {
+        let held = format.apply(account.get_held_in(currency));
+        let total = format.apply(account.get_total_in(currency));
+        CurrencySummary {
+            client: account.get_id(),
+            currency: currency.copied(),
+            available: total - held,
+            held,
+            total,
+            locked: account.is_locked(),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! How balances are rounded and printed
+
+use clap::ValueEnum;
+use rust_decimal::{Decimal, RoundingStrategy};
+
+/// How a value is brought down to the output scale
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Rounding {
+    /// Halves round to the nearest even digit, `0.00005 → 0.0000`, `0.00015 → 0.0002`
+    #[default]
+    Bankers,
+    /// Halves round away from zero, `0.00005 → 0.0001`
+    HalfUp,
+    /// Extra digits are dropped, `0.00019 → 0.0001`
+    Truncate,
+}
+
+/// The most decimal places a balance can be printed with
+///
+/// A [Decimal] holds 28 digits, so every balance below `10^16` still has room for them all.
+pub const MAX_SCALE: u32 = 12;
+
+/// A fixed number of decimal places and the rounding used to reach it
+///
+/// Every formatted value has exactly `scale` decimal places, so `2` is printed as `2.0000`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DecimalFormat {
+    pub scale: u32,
+    pub rounding: Rounding,
+}
+
+impl Default for DecimalFormat {
+    fn default() -> Self {
+        DecimalFormat {
+            scale: 4,
+            rounding: Rounding::default(),
+        }
+    }
+}
+
+impl DecimalFormat {
+    pub fn apply(&self, value: Decimal) -> Decimal {
+        let strategy = match self.rounding {
+            Rounding::Bankers => RoundingStrategy::BankersRounding,
+            Rounding::HalfUp => RoundingStrategy::RoundHalfUp,
+            Rounding::Truncate => RoundingStrategy::RoundDown,
+        };
+        let mut value = value.round_dp_with_strategy(self.scale, strategy);
+        value.rescale(self.scale);
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::{DecimalFormat, Rounding, MAX_SCALE};
+
+    fn format(value: &str, scale: u32, rounding: Rounding) -> String {
+        let value: Decimal = value.parse().unwrap();
+        DecimalFormat { scale, rounding }.apply(value).to_string()
+    }
+
+    #[test]
+    fn fixed_scale() {
+        assert_eq!(format("2", 4, Rounding::Bankers), "2.0000");
+        assert_eq!(format("0", 4, Rounding::Bankers), "0.0000");
+        assert_eq!(format("0.5", 2, Rounding::Bankers), "0.50");
+        assert_eq!(format("1.5", 0, Rounding::Bankers), "2");
+    }
+
+    #[test]
+    fn large_balances_keep_the_largest_scale() {
+        let formatted = format("9999999999999999.5", MAX_SCALE, Rounding::Bankers);
+        assert_eq!(formatted, "9999999999999999.500000000000");
+    }
+
+    #[test]
+    fn rounding_strategies() {
+        assert_eq!(format("0.00005", 4, Rounding::Bankers), "0.0000");
+        assert_eq!(format("0.00015", 4, Rounding::Bankers), "0.0002");
+        assert_eq!(format("0.00005", 4, Rounding::HalfUp), "0.0001");
+        assert_eq!(format("0.00019", 4, Rounding::Truncate), "0.0001");
+        assert_eq!(format("-0.00019", 4, Rounding::Truncate), "-0.0001");
+    }
+}
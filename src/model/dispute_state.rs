@@ -0,0 +1,56 @@
+//! Where a deposit or withdrawal is in the dispute lifecycle
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::transaction_type::TransactionType;
+
+/// The dispute lifecycle of a single processed transaction
+///
+/// `Processed → Disputed → Resolved | ChargedBack`
+///
+/// A resolved transaction may be disputed again, depending on the
+/// [RedisputePolicy](crate::model::policy::redispute_policy::RedisputePolicy).
+/// A charged back transaction is final.
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeState {
+    Processed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl DisputeState {
+    /// The state a transaction moves to when a transaction of the given type refers to it
+    ///
+    /// Other transactions don't refer to a disputable transaction, so they return [None].
+    pub fn entered_by(transaction_type: TransactionType) -> Option<DisputeState> {
+        match transaction_type {
+            TransactionType::Dispute => Some(DisputeState::Disputed),
+            TransactionType::Resolve => Some(DisputeState::Resolved),
+            TransactionType::Chargeback => Some(DisputeState::ChargedBack),
+            TransactionType::Deposit
+            | TransactionType::Withdraw
+            | TransactionType::Transfer
+            | TransactionType::Fee
+            | TransactionType::Interest
+            | TransactionType::Convert
+            | TransactionType::Freeze
+            | TransactionType::Unlock => None,
+        }
+    }
+}
+
+impl fmt::Display for DisputeState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DisputeState::Processed => "processed",
+            DisputeState::Disputed => "disputed",
+            DisputeState::Resolved => "resolved",
+            DisputeState::ChargedBack => "charged_back",
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -1,3 +1,6 @@
 //! Error types for use in [Results](Result)
 
+pub mod parse_error;
+pub mod snapshot_error;
+pub mod spreadsheet_error;
 pub mod transaction_error;
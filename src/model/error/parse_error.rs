@@ -0,0 +1,29 @@
+use csv::StringRecord;
+use thiserror::Error;
+
+/// A row of input that could not be read as a transaction
+///
+/// Such as an unknown `type`, a non-numeric `client`, a malformed `amount`
+/// or the wrong number of columns.
+#[derive(Error, Debug, Clone)]
+#[error("Could not parse line {line} (byte {byte}): {message}")]
+pub struct ParseError {
+    pub line: u64,
+    pub byte: u64,
+    pub message: String,
+}
+
+impl ParseError {
+    /// Takes the position from the csv error when it has one, or the record otherwise
+    pub fn new(err: &csv::Error, record: &StringRecord) -> ParseError {
+        let position = err.position().or_else(|| record.position());
+        ParseError {
+            line: position.map_or(0, |position| position.line()),
+            byte: position.map_or(0, |position| position.byte()),
+            message: match err.kind() {
+                csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+                _ => err.to_string(),
+            },
+        }
+    }
+}
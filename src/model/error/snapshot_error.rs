@@ -0,0 +1,18 @@
+use std::io;
+
+use thiserror::Error;
+
+/// Reasons a snapshot could not be saved or loaded
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("Could not read or write the snapshot: {0}")]
+    Io(#[from] io::Error),
+    #[error("Not a snapshot file")]
+    NotASnapshot,
+    #[error("Snapshot is version {found}, but only version {expected} can be loaded")]
+    UnsupportedVersion { found: String, expected: u32 },
+    #[error("Snapshot is corrupt, its checksum is {found:08x} instead of {expected:08x}")]
+    ChecksumMismatch { expected: u32, found: u32 },
+    #[error("Snapshot could not be parsed: {0}")]
+    Malformed(#[from] serde_json::Error),
+}
@@ -0,0 +1,17 @@
+use std::io;
+
+use thiserror::Error;
+
+use super::{parse_error::ParseError, snapshot_error::SnapshotError};
+
+/// Reasons processing a spreadsheet stopped early
+#[derive(Error, Debug)]
+pub enum SpreadsheetError {
+    #[error("Could not read or write: {0}")]
+    Io(#[from] io::Error),
+    /// Only returned in strict mode, otherwise malformed rows are reported and skipped
+    #[error("{0}")]
+    Malformed(#[from] ParseError),
+    #[error("{0}")]
+    Snapshot(#[from] SnapshotError),
+}
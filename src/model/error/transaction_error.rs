@@ -1,17 +1,208 @@
+use std::io;
+
+use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
 use thiserror::Error;
 
+use crate::model::{
+    currency::Currency,
+    id::{client_id::ClientId, transaction_id::TransactionId},
+    transaction_type::TransactionType,
+};
+
 #[derive(Error, Debug)]
 pub enum TransactionError {
-    #[error("Tried to withdraw {transaction_amount} from an available balance of {available}")]
+    #[error("Tried to withdraw {transaction_amount} from an available balance of {available} with an overdraft limit of {limit}")]
     Overdraft {
         available: Decimal,
         transaction_amount: Decimal,
+        limit: Decimal,
+    },
+    #[error("Transaction {tx} does not exist for this client")]
+    TransactionDoesNotExist { tx: TransactionId },
+    #[error("Transaction {tx} is already disputed")]
+    AlreadyDisputed { tx: TransactionId },
+    #[error("Transaction {tx} is not disputed")]
+    NotDisputed { tx: TransactionId },
+    #[error("Transaction {tx} was already resolved and can't be disputed again")]
+    AlreadyResolved { tx: TransactionId },
+    #[error("Transaction {tx} was already charged back")]
+    AlreadyChargedBack { tx: TransactionId },
+    #[error("Transaction {tx} could only be disputed until {deadline}")]
+    DisputeWindowClosed {
+        tx: TransactionId,
+        deadline: DateTime<Utc>,
+    },
+    #[error("Tried to charge a fee of {fee} to an available balance of {available}, which may only go {limit} below zero")]
+    FeeLimit {
+        available: Decimal,
+        fee: Decimal,
+        limit: Decimal,
+    },
+    #[error("Transaction {tx} already exists")]
+    DuplicateTransaction { tx: TransactionId },
+    #[error("Transaction {tx} is a {transaction_type}, which can't be disputed")]
+    NotDisputable {
+        tx: TransactionId,
+        transaction_type: TransactionType,
+    },
+    #[error("Transaction {tx} is in {}, not {found}", Currency::name(.expected.as_ref()))]
+    CurrencyMismatch {
+        tx: TransactionId,
+        expected: Option<Currency>,
+        found: Currency,
+    },
+    #[error("There is no rate to convert from {} to {}", Currency::name(.from.as_ref()), Currency::name(.to.as_ref()))]
+    NoRate {
+        from: Option<Currency>,
+        to: Option<Currency>,
+    },
+    #[error("Transaction at {timestamp} is earlier than the last one applied, at {latest}")]
+    OutOfOrder {
+        timestamp: DateTime<Utc>,
+        latest: DateTime<Utc>,
     },
-    #[error("Transaction does not exist")]
-    TransactionDoesNotExist,
-    #[error("Transaction either does not exist or is not disputed")]
-    TransactionNotDisputed,
     #[error("Account is locked")]
     AccountLocked,
+    #[error("Account is not locked")]
+    AccountNotLocked,
+    #[error("Transaction has no reason")]
+    MissingReason,
+    #[error("Transfer has no destination")]
+    MissingDestination,
+    #[error("Transfer has the same client as its source and destination")]
+    SelfTransfer,
+    #[error("Transaction should not have a destination, but has {destination}")]
+    UnexpectedDestination { destination: ClientId },
+    #[error("Conversion has the same currency on both sides")]
+    SameCurrency,
+    #[error("Transaction should not have a currency to convert to, but has {to_currency}")]
+    UnexpectedTargetCurrency { to_currency: Currency },
+    #[error("Destination account {destination} is locked")]
+    DestinationLocked { destination: ClientId },
+    #[error("Transaction has no amount")]
+    MissingAmount,
+    #[error("Amount {amount} is not positive")]
+    NonPositiveAmount { amount: Decimal },
+    #[error("Amount {amount} has more than {max_scale} decimal places")]
+    ExcessPrecision { amount: Decimal, max_scale: u32 },
+    #[error("Amount {amount} is more than the largest allowed, {max}")]
+    AmountTooLarge { amount: Decimal, max: Decimal },
+    #[error("Transaction should not have an amount, but has {amount}")]
+    UnexpectedAmount { amount: Decimal },
+    #[error("Could not access the transaction history: {0}")]
+    Storage(#[from] io::Error),
+}
+
+impl TransactionError {
+    /// The name of the variant, for use in machine-readable reports
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionError::Overdraft { .. } => "Overdraft",
+            TransactionError::TransactionDoesNotExist { .. } => "TransactionDoesNotExist",
+            TransactionError::AlreadyDisputed { .. } => "AlreadyDisputed",
+            TransactionError::NotDisputed { .. } => "NotDisputed",
+            TransactionError::AlreadyResolved { .. } => "AlreadyResolved",
+            TransactionError::AlreadyChargedBack { .. } => "AlreadyChargedBack",
+            TransactionError::DisputeWindowClosed { .. } => "DisputeWindowClosed",
+            TransactionError::FeeLimit { .. } => "FeeLimit",
+            TransactionError::DuplicateTransaction { .. } => "DuplicateTransaction",
+            TransactionError::NotDisputable { .. } => "NotDisputable",
+            TransactionError::CurrencyMismatch { .. } => "CurrencyMismatch",
+            TransactionError::NoRate { .. } => "NoRate",
+            TransactionError::OutOfOrder { .. } => "OutOfOrder",
+            TransactionError::AccountLocked => "AccountLocked",
+            TransactionError::AccountNotLocked => "AccountNotLocked",
+            TransactionError::MissingReason => "MissingReason",
+            TransactionError::MissingDestination => "MissingDestination",
+            TransactionError::SelfTransfer => "SelfTransfer",
+            TransactionError::UnexpectedDestination { .. } => "UnexpectedDestination",
+            TransactionError::SameCurrency => "SameCurrency",
+            TransactionError::UnexpectedTargetCurrency { .. } => "UnexpectedTargetCurrency",
+            TransactionError::DestinationLocked { .. } => "DestinationLocked",
+            TransactionError::MissingAmount => "MissingAmount",
+            TransactionError::NonPositiveAmount { .. } => "NonPositiveAmount",
+            TransactionError::ExcessPrecision { .. } => "ExcessPrecision",
+            TransactionError::AmountTooLarge { .. } => "AmountTooLarge",
+            TransactionError::UnexpectedAmount { .. } => "UnexpectedAmount",
+            TransactionError::Storage(_) => "Storage",
+        }
+    }
+
+    /// The data carried by the variant as `(field, value)` pairs
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        match self {
+            TransactionError::Overdraft {
+                available,
+                transaction_amount,
+                limit,
+            } => vec![
+                ("available", available.to_string()),
+                ("transaction_amount", transaction_amount.to_string()),
+                ("limit", limit.to_string()),
+            ],
+            TransactionError::FeeLimit {
+                available,
+                fee,
+                limit,
+            } => vec![
+                ("available", available.to_string()),
+                ("fee", fee.to_string()),
+                ("limit", limit.to_string()),
+            ],
+            TransactionError::TransactionDoesNotExist { tx }
+            | TransactionError::AlreadyDisputed { tx }
+            | TransactionError::NotDisputed { tx }
+            | TransactionError::AlreadyResolved { tx }
+            | TransactionError::AlreadyChargedBack { tx }
+            | TransactionError::DuplicateTransaction { tx } => vec![("tx", tx.to_string())],
+            TransactionError::DisputeWindowClosed { tx, deadline } => {
+                vec![("tx", tx.to_string()), ("deadline", deadline.to_rfc3339())]
+            }
+            TransactionError::NotDisputable {
+                tx,
+                transaction_type,
+            } => vec![
+                ("tx", tx.to_string()),
+                ("transaction_type", transaction_type.to_string()),
+            ],
+            TransactionError::NonPositiveAmount { amount }
+            | TransactionError::UnexpectedAmount { amount } => {
+                vec![("amount", amount.to_string())]
+            }
+            TransactionError::ExcessPrecision { amount, max_scale } => vec![
+                ("amount", amount.to_string()),
+                ("max_scale", max_scale.to_string()),
+            ],
+            TransactionError::AmountTooLarge { amount, max } => {
+                vec![("amount", amount.to_string()), ("max", max.to_string())]
+            }
+            TransactionError::CurrencyMismatch {
+                tx,
+                expected,
+                found,
+            } => vec![
+                ("tx", tx.to_string()),
+                ("expected", Currency::name(expected.as_ref()).to_string()),
+                ("found", found.to_string()),
+            ],
+            TransactionError::UnexpectedDestination { destination }
+            | TransactionError::DestinationLocked { destination } => {
+                vec![("destination", destination.to_string())]
+            }
+            TransactionError::NoRate { from, to } => vec![
+                ("from", Currency::name(from.as_ref()).to_string()),
+                ("to", Currency::name(to.as_ref()).to_string()),
+            ],
+            TransactionError::OutOfOrder { timestamp, latest } => vec![
+                ("timestamp", timestamp.to_rfc3339()),
+                ("latest", latest.to_rfc3339()),
+            ],
+            TransactionError::UnexpectedTargetCurrency { to_currency } => {
+                vec![("to_currency", to_currency.to_string())]
+            }
+            TransactionError::Storage(err) => vec![("cause", err.to_string())],
+            _ => vec![],
+        }
+    }
 }
@@ -0,0 +1,35 @@
+//! One exchange rate, loaded separately from the transactions
+
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{de, Deserialize, Deserializer};
+
+use super::currency::Currency;
+
+/// One row of the rates file
+///
+/// An amount in `from` multiplied by `rate` is the amount in `to`. Either currency can be
+/// left empty for the base currency. A rate is used from its `effective` time until the next
+/// rate for the same pair takes over.
+///
+/// ```csv,no_run
+/// from, to,  rate,   effective
+/// EUR,  ,    1.0842, 2024-05-01T00:00:00Z
+/// EUR,  GBP, 0.8561, 2024-05-01T00:00:00Z
+/// ```
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct ExchangeRate {
+    pub from: Option<Currency>,
+    pub to: Option<Currency>,
+    #[serde(deserialize_with = "positive")]
+    pub rate: Decimal,
+    pub effective: DateTime<Utc>,
+}
+
+fn positive<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+    let rate = <Decimal as Deserialize>::deserialize(deserializer)?;
+    if rate <= Decimal::from(0) {
+        return Err(de::Error::custom(format!("rate {} is not positive", rate)));
+    }
+    Ok(rate)
+}
@@ -0,0 +1,13 @@
+//! An account's money in a single currency
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use super::activity::Activity;
+
+/// The settled balance and activity of an [Account](super::account::Account) in one currency
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ledger {
+    pub balance: Decimal,
+    pub activity: Activity,
+}
@@ -1,5 +1,21 @@
 pub mod account;
+pub mod account_summary;
+pub mod activity;
+pub mod activity_summary;
+pub mod audit_entry;
+pub mod balances;
+pub mod client_settings;
+pub mod currency;
+pub mod currency_summary;
+pub mod decimal_format;
+pub mod dispute_state;
 pub mod error;
+pub mod exchange_rate;
 pub mod id;
+pub mod ledger;
+pub mod policy;
+pub mod rate_table;
+pub mod timeline;
 pub mod transaction;
+pub mod transaction_record;
 pub mod transaction_type;
@@ -0,0 +1,14 @@
+use rust_decimal::Decimal;
+
+use super::withdrawal_dispute_policy::WithdrawalDisputePolicy;
+
+/// The rules an [Account](crate::model::account::Account) applies transactions by,
+/// set on the [Store](crate::store::Store) for every account
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct AccountPolicy {
+    pub withdrawal_disputes: WithdrawalDisputePolicy,
+    /// How far below zero a fee may take the available balance
+    ///
+    /// Withdrawals and transfers can never take it below zero.
+    pub fee_limit: Decimal,
+}
@@ -0,0 +1,16 @@
+use clap::ValueEnum;
+
+/// What the [Store](crate::store::Store) should do with a deposit or withdrawal whose
+/// transaction id has already been used.
+///
+/// Transaction ids are unique across all clients, not just within a single account.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum DuplicatePolicy {
+    /// Refuse the transaction with [DuplicateTransaction](crate::model::error::transaction_error::TransactionError::DuplicateTransaction)
+    #[default]
+    Reject,
+    /// Silently skip the transaction, keeping the original
+    Ignore,
+    /// Skip the transaction if it is an exact replay of the original, otherwise reject it
+    Idempotent,
+}
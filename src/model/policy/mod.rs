@@ -0,0 +1,6 @@
+//! Configurable rules for how the [Store](crate::store::Store) handles edge cases
+
+pub mod account_policy;
+pub mod duplicate_policy;
+pub mod redispute_policy;
+pub mod withdrawal_dispute_policy;
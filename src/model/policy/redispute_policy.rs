@@ -0,0 +1,14 @@
+use clap::ValueEnum;
+
+/// Whether the [Store](crate::store::Store) lets a client dispute a transaction again
+/// after an earlier dispute of it was resolved
+///
+/// Charged back transactions can never be disputed again.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum RedisputePolicy {
+    /// Resolved transactions can be disputed again, any number of times
+    #[default]
+    Allow,
+    /// Refuse the dispute with [AlreadyResolved](crate::model::error::transaction_error::TransactionError::AlreadyResolved)
+    Reject,
+}
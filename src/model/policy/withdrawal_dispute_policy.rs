@@ -0,0 +1,17 @@
+use clap::ValueEnum;
+
+/// What the [Store](crate::store::Store) should do when a client disputes a withdrawal
+///
+/// Disputing a deposit holds the deposited funds until the dispute is resolved or charged back.
+/// A disputed withdrawal is the client claiming money left their account that shouldn't have.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum WithdrawalDisputePolicy {
+    /// Refuse the dispute with [NotDisputable](crate::model::error::transaction_error::TransactionError::NotDisputable)
+    #[default]
+    Reject,
+    /// Credit the withdrawn amount back into held funds while the dispute is open
+    ///
+    /// Resolving the dispute removes the credit again. A chargeback makes the credit
+    /// available to the client and locks the account.
+    ProvisionalCredit,
+}
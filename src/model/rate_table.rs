@@ -0,0 +1,90 @@
+//! Exchange rates over time, for converting between currencies
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+
+use super::{currency::Currency, exchange_rate::ExchangeRate};
+
+/// The currency converted from and the currency converted to, [None] for the base currency
+type Pair = (Option<Currency>, Option<Currency>);
+
+/// Every known [ExchangeRate], by currency pair and the time it took effect
+///
+/// Rates are only looked up in the direction they were given, converting back needs a rate
+/// of its own.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RateTable {
+    rates: BTreeMap<Pair, BTreeMap<DateTime<Utc>, Decimal>>,
+}
+
+impl RateTable {
+    /// A table of the given rates, a later rate for the same pair and time replaces an earlier one
+    pub fn new(rates: Vec<ExchangeRate>) -> RateTable {
+        let mut table = RateTable::default();
+        for rate in rates {
+            table
+                .rates
+                .entry((rate.from, rate.to))
+                .or_default()
+                .insert(rate.effective, rate.rate);
+        }
+        table
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rates.is_empty()
+    }
+
+    /// The rate in effect for a pair at the given time, or the latest rate without one
+    pub fn rate(
+        &self,
+        from: Option<Currency>,
+        to: Option<Currency>,
+        at: Option<DateTime<Utc>>,
+    ) -> Option<Decimal> {
+        let rates = self.rates.get(&(from, to))?;
+        let rate = match at {
+            Some(at) => rates.range(..=at).next_back(),
+            None => rates.iter().next_back(),
+        };
+        rate.map(|(_, rate)| *rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+
+    use crate::model::exchange_rate::ExchangeRate;
+
+    use super::RateTable;
+
+    fn time(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn looks_up_the_rate_in_effect() {
+        let eur = Some("EUR".parse().unwrap());
+        let rate = |rate: &str, effective| ExchangeRate {
+            from: eur,
+            to: None,
+            rate: rate.parse().unwrap(),
+            effective: time(effective),
+        };
+        let table = RateTable::new(vec![
+            rate("1.10", "2024-05-02T00:00:00Z"),
+            rate("1.05", "2024-05-01T00:00:00Z"),
+        ]);
+
+        let at = |at| table.rate(eur, None, Some(time(at))).map(|r| r.to_string());
+        assert_eq!(at("2024-04-30T12:00:00Z"), None);
+        assert_eq!(at("2024-05-01T00:00:00Z"), Some("1.05".to_string()));
+        assert_eq!(at("2024-05-01T23:59:59Z"), Some("1.05".to_string()));
+        assert_eq!(at("2024-06-01T00:00:00Z"), Some("1.10".to_string()));
+        assert_eq!(table.rate(eur, None, None), Some("1.10".parse().unwrap()));
+        assert_eq!(table.rate(None, eur, None), None);
+    }
+}
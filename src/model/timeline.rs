@@ -0,0 +1,52 @@
+//! Balances over time, for looking up what an account held in the past
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+
+use super::{account::Account, balances::Balances, currency::Currency, id::client_id::ClientId};
+
+/// An account's [Balances] in each of its currencies, [None] for the base currency
+pub type Snapshot = BTreeMap<Option<Currency>, Balances>;
+
+/// A [Snapshot] of an account every time a timestamped transaction changed it
+///
+/// Only the last snapshot at any one time is kept, so a lookup sees every transaction with
+/// that timestamp.
+#[derive(Debug, Default)]
+pub struct Timeline {
+    snapshots: BTreeMap<ClientId, BTreeMap<DateTime<Utc>, Snapshot>>,
+}
+
+impl Timeline {
+    /// Keeps the account's current balances as its balances at the given time
+    pub fn record(&mut self, at: DateTime<Utc>, account: &Account) {
+        let snapshot = account
+            .get_currencies()
+            .map(|currency| (currency.copied(), account.get_balances_in(currency)))
+            .collect();
+        self.snapshots
+            .entry(account.get_id())
+            .or_default()
+            .insert(at, snapshot);
+    }
+
+    /// Takes over the balances of the clients `owns` picks from another timeline
+    pub fn absorb<F: Fn(ClientId) -> bool>(&mut self, other: Timeline, owns: F) {
+        for (client, snapshots) in other.snapshots {
+            if owns(client) {
+                self.snapshots.entry(client).or_default().extend(snapshots);
+            }
+        }
+    }
+
+    /// The client's balances as of the given time, [None] if they had no timestamped
+    /// transactions by then
+    pub fn balances_at(&self, client: ClientId, at: DateTime<Utc>) -> Option<&Snapshot> {
+        self.snapshots
+            .get(&client)?
+            .range(..=at)
+            .next_back()
+            .map(|(_, snapshot)| snapshot)
+    }
+}
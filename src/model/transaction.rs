@@ -1,16 +1,292 @@
+use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use super::{
+    currency::Currency,
+    error::transaction_error::TransactionError,
     id::{client_id::ClientId, transaction_id::TransactionId},
     transaction_type::TransactionType,
 };
 
-#[derive(Debug, Deserialize, Clone)]
+/// The most decimal places an amount may have
+pub const MAX_AMOUNT_SCALE: u32 = 4;
+
+/// The largest amount a transaction may have, so that balances can't overflow
+pub const MAX_AMOUNT: i64 = 1_000_000_000_000;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Transaction {
-    #[serde(rename(deserialize = "type"))]
+    #[serde(rename = "type")]
     pub transaction_type: TransactionType,
     pub client: ClientId,
     pub tx: TransactionId,
     pub amount: Option<Decimal>,
+    /// The client receiving a [Transfer](TransactionType::Transfer)
+    ///
+    /// The column can be left out of inputs that have no transfers.
+    #[serde(default)]
+    pub destination: Option<ClientId>,
+    /// The currency of the amount, or of the transaction referred to, [None] for the base currency
+    ///
+    /// The column can be left out of inputs that only use the base currency.
+    #[serde(default)]
+    pub currency: Option<Currency>,
+    /// The currency a [Convert](TransactionType::Convert) exchanges its amount into, [None] for
+    /// the base currency
+    ///
+    /// The column can be left out of inputs that have no conversions.
+    #[serde(default)]
+    pub to_currency: Option<Currency>,
+    /// The exchange rate a [Convert](TransactionType::Convert) was applied at
+    ///
+    /// Filled in by the [Store](crate::store::Store) from its rates before the conversion is
+    /// applied and stored, a rate given in the input is ignored.
+    #[serde(default)]
+    pub rate: Option<Decimal>,
+    /// Why an administrative transaction, such as a [Freeze](TransactionType::Freeze), was issued
+    ///
+    /// The column can be left out of inputs that have no administrative transactions.
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// When the transaction happened, as an RFC 3339 time such as `2024-05-01T09:30:00Z`
+    ///
+    /// The column can be left out, or left empty on some rows, of inputs that don't need times.
+    #[serde(default)]
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl Transaction {
+    /// Checks the transaction has the amount, destination, currencies and reason its type needs
+    pub fn validate(&self) -> Result<(), TransactionError> {
+        self.validate_amount()?;
+        match (self.transaction_type, self.destination) {
+            (TransactionType::Transfer, None) => return Err(TransactionError::MissingDestination),
+            (TransactionType::Transfer, Some(destination)) if destination == self.client => {
+                return Err(TransactionError::SelfTransfer)
+            }
+            (TransactionType::Transfer, Some(_)) | (_, None) => {}
+            (_, Some(destination)) => {
+                return Err(TransactionError::UnexpectedDestination { destination })
+            }
+        }
+        match (self.transaction_type, self.to_currency) {
+            (TransactionType::Convert, to_currency) if to_currency == self.currency => {
+                return Err(TransactionError::SameCurrency)
+            }
+            (TransactionType::Convert, _) | (_, None) => {}
+            (_, Some(to_currency)) => {
+                return Err(TransactionError::UnexpectedTargetCurrency { to_currency })
+            }
+        }
+        match self.transaction_type {
+            TransactionType::Freeze | TransactionType::Unlock if self.reason.is_none() => {
+                Err(TransactionError::MissingReason)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The amount a [Convert](TransactionType::Convert) credits in its `to_currency`
+    ///
+    /// Worked out from the recorded `rate`, so it is the same every time it is asked for,
+    /// and rounded to [MAX_AMOUNT_SCALE] decimal places with banker's rounding.
+    /// [None] without a rate, or if the result is too large.
+    pub fn converted_amount(&self) -> Option<Decimal> {
+        let amount = self.amount?.checked_mul(self.rate?)?;
+        Some(amount.round_dp(MAX_AMOUNT_SCALE))
+    }
+
+    /// Checks the amount makes sense for the type of transaction
+    ///
+    /// Types that [have an amount](TransactionType::has_amount) need a positive one with at most
+    /// [MAX_AMOUNT_SCALE] decimal places, and no larger than [MAX_AMOUNT]. Disputes, resolves and chargebacks refer to another
+    /// transaction's amount, so they must not have one of their own, and neither must freezes
+    /// and unlocks.
+    pub fn validate_amount(&self) -> Result<(), TransactionError> {
+        if !self.transaction_type.has_amount() {
+            return match self.amount {
+                Some(amount) => Err(TransactionError::UnexpectedAmount { amount }),
+                None => Ok(()),
+            };
+        }
+        let amount = self.amount.ok_or(TransactionError::MissingAmount)?;
+        if amount <= Decimal::from(0) {
+            return Err(TransactionError::NonPositiveAmount { amount });
+        }
+        if amount > Decimal::from(MAX_AMOUNT) {
+            return Err(TransactionError::AmountTooLarge {
+                amount,
+                max: MAX_AMOUNT.into(),
+            });
+        }
+        if amount.normalize().scale() > MAX_AMOUNT_SCALE {
+            return Err(TransactionError::ExcessPrecision {
+                amount,
+                max_scale: MAX_AMOUNT_SCALE,
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether this transaction carries exactly the same payload as another.
+    ///
+    /// Amounts are compared by their underlying representation, so `1.0` and `1.00`
+    /// are considered different even though they are numerically equal.
+    pub fn is_replay_of(&self, other: &Transaction) -> bool {
+        self.transaction_type == other.transaction_type
+            && self.client == other.client
+            && self.tx == other.tx
+            && self.amount.map(|amount| amount.serialize())
+                == other.amount.map(|amount| amount.serialize())
+            && self.destination == other.destination
+            && self.currency == other.currency
+            && self.to_currency == other.to_currency
+            && self.timestamp == other.timestamp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::{
+        error::transaction_error::TransactionError,
+        id::{client_id::ClientId, transaction_id::TransactionId},
+        transaction_type::TransactionType,
+    };
+
+    use super::Transaction;
+
+    fn validate(
+        transaction_type: TransactionType,
+        amount: Option<&str>,
+    ) -> Result<(), TransactionError> {
+        Transaction {
+            transaction_type,
+            client: ClientId(1),
+            tx: TransactionId(1),
+            amount: amount.map(|amount| amount.parse().unwrap()),
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            reason: None,
+            timestamp: None,
+        }
+        .validate_amount()
+    }
+
+    #[test]
+    fn validates_deposit_and_withdraw_amounts() {
+        for transaction_type in [TransactionType::Deposit, TransactionType::Withdraw] {
+            assert!(validate(transaction_type, Some("1.2345")).is_ok());
+            assert!(validate(transaction_type, Some("1.23450000")).is_ok());
+            assert!(matches!(
+                validate(transaction_type, None),
+                Err(TransactionError::MissingAmount)
+            ));
+            assert!(matches!(
+                validate(transaction_type, Some("0")),
+                Err(TransactionError::NonPositiveAmount { .. })
+            ));
+            assert!(matches!(
+                validate(transaction_type, Some("-5")),
+                Err(TransactionError::NonPositiveAmount { .. })
+            ));
+            assert!(matches!(
+                validate(transaction_type, Some("1.23456")),
+                Err(TransactionError::ExcessPrecision { max_scale: 4, .. })
+            ));
+            assert!(validate(transaction_type, Some("1000000000000")).is_ok());
+            for amount in ["1000000000000.0001", "50000000000000000000000000000"] {
+                assert!(matches!(
+                    validate(transaction_type, Some(amount)),
+                    Err(TransactionError::AmountTooLarge { .. })
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_amounts_on_references() {
+        for transaction_type in [
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Chargeback,
+        ] {
+            assert!(validate(transaction_type, None).is_ok());
+            assert!(matches!(
+                validate(transaction_type, Some("1")),
+                Err(TransactionError::UnexpectedAmount { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn validates_transfer_destinations() {
+        let transfer = |destination: Option<u16>| Transaction {
+            transaction_type: TransactionType::Transfer,
+            client: ClientId(1),
+            tx: TransactionId(1),
+            amount: Some(5.into()),
+            destination: destination.map(ClientId),
+            currency: None,
+            to_currency: None,
+            rate: None,
+            reason: None,
+            timestamp: None,
+        };
+        assert!(transfer(Some(2)).validate().is_ok());
+        assert!(matches!(
+            transfer(None).validate(),
+            Err(TransactionError::MissingDestination)
+        ));
+        assert!(matches!(
+            transfer(Some(1)).validate(),
+            Err(TransactionError::SelfTransfer)
+        ));
+
+        let deposit = Transaction {
+            transaction_type: TransactionType::Deposit,
+            ..transfer(Some(2))
+        };
+        assert!(matches!(
+            deposit.validate(),
+            Err(TransactionError::UnexpectedDestination { .. })
+        ));
+    }
+
+    #[test]
+    fn validates_and_converts_currencies() {
+        let eur = "EUR".parse().unwrap();
+        let convert = |to_currency| Transaction {
+            transaction_type: TransactionType::Convert,
+            client: ClientId(1),
+            tx: TransactionId(1),
+            amount: Some("3.3333".parse().unwrap()),
+            destination: None,
+            currency: None,
+            to_currency,
+            rate: Some("1.0837".parse().unwrap()),
+            reason: None,
+            timestamp: None,
+        };
+        assert!(convert(Some(eur)).validate().is_ok());
+        assert!(matches!(
+            convert(None).validate(),
+            Err(TransactionError::SameCurrency)
+        ));
+        assert_eq!(
+            convert(Some(eur)).converted_amount(),
+            Some("3.6123".parse().unwrap())
+        );
+
+        let deposit = Transaction {
+            transaction_type: TransactionType::Deposit,
+            ..convert(Some(eur))
+        };
+        assert!(matches!(
+            deposit.validate(),
+            Err(TransactionError::UnexpectedTargetCurrency { .. })
+        ));
+    }
 }
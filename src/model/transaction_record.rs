@@ -0,0 +1,48 @@
+//! A stored transaction along with its dispute history
+
+use serde::{Deserialize, Serialize};
+
+use super::{dispute_state::DisputeState, transaction::Transaction};
+
+/// A processed deposit or withdrawal, as kept in the
+/// [TransactionHistory](crate::history::TransactionHistory)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TransactionRecord {
+    pub transaction: Transaction,
+    /// Every state the transaction has been in, oldest first
+    states: Vec<DisputeState>,
+}
+
+impl TransactionRecord {
+    /// A freshly processed transaction that has never been disputed
+    pub fn new(transaction: Transaction) -> TransactionRecord {
+        TransactionRecord {
+            transaction,
+            states: vec![DisputeState::Processed],
+        }
+    }
+
+    /// The current state
+    pub fn state(&self) -> DisputeState {
+        self.states
+            .last()
+            .copied()
+            .unwrap_or(DisputeState::Processed)
+    }
+
+    /// Every state the transaction has been in, oldest first
+    pub fn get_states(&self) -> &[DisputeState] {
+        &self.states
+    }
+
+    /// Moves the transaction to a new state, keeping the old one in its history
+    pub fn transition(&mut self, state: DisputeState) {
+        self.states.push(state);
+    }
+}
+
+impl From<Transaction> for TransactionRecord {
+    fn from(transaction: Transaction) -> TransactionRecord {
+        TransactionRecord::new(transaction)
+    }
+}
@@ -1,16 +1,75 @@
 //! Enum for transactions. Deposit, Withdraw, Dispute...
 
-use serde::Deserialize;
+use std::fmt;
 
-/// These values will be parsed from lowercase values
+use serde::{Deserialize, Serialize};
+
+/// These values will be parsed from and written as lowercase values
 ///
 /// `"deposit" → TransactionType::Deposit`
-#[derive(Debug, Deserialize, Copy, Clone)]
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
     Withdraw,
+    /// Moves funds from the client's account to the `destination` client's account
+    Transfer,
+    /// Charges the client, possibly taking the account below zero
+    Fee,
+    /// Credits the client, and can't be disputed
+    Interest,
+    /// Exchanges an amount of the client's money in one currency for another currency
+    Convert,
     Dispute,
     Resolve,
     Chargeback,
+    /// Locks the account, issued by an administrator with a reason
+    ///
+    /// Its `tx` isn't checked for duplicates or kept, the same id can be used again.
+    Freeze,
+    /// Lifts the lock on an account, issued by an administrator with a reason
+    ///
+    /// Like a [Freeze](TransactionType::Freeze), its `tx` is exempt from duplicate checks.
+    Unlock,
+}
+
+impl TransactionType {
+    /// Whether transactions of this type move money with an amount of their own
+    ///
+    /// These are kept in the [TransactionHistory](crate::history::TransactionHistory),
+    /// while the other types refer to one of them or act on the account as a whole.
+    pub fn has_amount(self) -> bool {
+        match self {
+            TransactionType::Deposit
+            | TransactionType::Withdraw
+            | TransactionType::Transfer
+            | TransactionType::Fee
+            | TransactionType::Interest
+            | TransactionType::Convert => true,
+            TransactionType::Dispute
+            | TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::Freeze
+            | TransactionType::Unlock => false,
+        }
+    }
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdraw => "withdraw",
+            TransactionType::Transfer => "transfer",
+            TransactionType::Fee => "fee",
+            TransactionType::Interest => "interest",
+            TransactionType::Convert => "convert",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Freeze => "freeze",
+            TransactionType::Unlock => "unlock",
+        };
+        write!(f, "{}", name)
+    }
 }
@@ -0,0 +1,574 @@
+//! Applies rows on several threads at once, with each client's rows on the same thread
+//!
+//! Rows are parsed on the calling thread and handed to one of several shards, each a
+//! [Store] of its own on a thread of its own, picked by the row's client. A client's rows
+//! are always applied by the same shard in the order they were read, and their outcomes are
+//! reported in that order too, so the results are the same as applying every row in turn.
+//!
+//! A few rows depend on more than one shard:
+//! - a transfer to a client of another shard
+//! - a transaction reusing the id of one that another shard has seen
+//! - a timestamped row earlier than one already handed over
+//!
+//! For those, every shard is first left to finish the rows it was given. Then a transfer is
+//! applied with the destination account brought over from its shard and back again, a reused
+//! id is checked against copies of the other shards' transactions, and a timestamp against
+//! the latest one any shard applied.
+//!
+//! Disputes expire as time moves on for every client at once, so a store with a
+//! [dispute expiry](Store::with_dispute_expiry) can't be sharded.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        Mutex,
+    },
+    thread,
+};
+
+use chrono::{DateTime, Utc};
+use csv::StringRecord;
+
+use crate::{
+    model::{
+        audit_entry::AuditEntry,
+        error::{
+            parse_error::ParseError, spreadsheet_error::SpreadsheetError,
+            transaction_error::TransactionError,
+        },
+        id::{client_id::ClientId, transaction_id::TransactionId},
+        transaction::Transaction,
+    },
+    report::{ErrorRecord, ErrorReport},
+    spreadsheet::{read_rows, Summary},
+    store::Store,
+};
+
+/// How many rows are handed to a shard at a time
+const BATCH_SIZE: usize = 256;
+
+/// How many batches each shard can have waiting before reading pauses
+const QUEUE_LENGTH: usize = 4;
+
+/// Marks a transaction id seen by more than one shard
+pub const SHARED: usize = usize::MAX;
+
+/// What became of a row
+#[derive(Debug)]
+enum Outcome {
+    Malformed(ParseError),
+    /// Whether the store applied the row, and the audit entries it made
+    Applied(Result<(), TransactionError>, Vec<AuditEntry>),
+}
+
+/// Rows handed to a shard together, each with its row number
+type Batch = Vec<(u64, Transaction)>;
+
+/// The shard that saw each transaction id that moves money, or [SHARED]
+pub type Seen = HashMap<TransactionId, usize>;
+
+/// The shards of a [Store], kept between inputs
+#[derive(Debug)]
+pub struct Pipeline {
+    shards: Vec<Mutex<Store>>,
+    seen: Seen,
+    /// The latest timestamp handed to any shard
+    latest: Option<DateTime<Utc>>,
+    /// Audit entries made by every shard, in the order their rows were read
+    audit_log: Vec<AuditEntry>,
+}
+
+impl Pipeline {
+    /// Splits the store's accounts and transactions into the given number of shards
+    ///
+    /// Fails for a store with a [dispute expiry](Store::with_dispute_expiry), see [split].
+    pub fn new(store: &Store, threads: usize) -> io::Result<Pipeline> {
+        let (shards, seen) = split(store, threads)?;
+        Ok(Pipeline {
+            shards,
+            seen,
+            latest: store.get_latest(),
+            audit_log: Vec::new(),
+        })
+    }
+
+    /// Applies every row, reporting outcomes like [read_spreadsheet](crate::spreadsheet::read_spreadsheet)
+    ///
+    /// In `strict` mode the first malformed row is reported, once every row before it is
+    /// applied, and then stops processing.
+    pub fn read<T: io::Read, W: io::Write>(
+        &mut self,
+        reader: &mut csv::Reader<T>,
+        report: &mut ErrorReport<W>,
+        strict: bool,
+        reorder_window: usize,
+    ) -> Result<Summary, SpreadsheetError> {
+        let Pipeline {
+            shards,
+            seen,
+            latest,
+            audit_log,
+        } = self;
+        let shards = &shards[..];
+        thread::scope(|scope| {
+            let (results, outcomes) = mpsc::channel();
+            let queues = shards
+                .iter()
+                .map(|shard| {
+                    let (queue, rows) = mpsc::sync_channel(QUEUE_LENGTH);
+                    let results = results.clone();
+                    scope.spawn(move || work(shard, rows, results));
+                    queue
+                })
+                .collect();
+            drop(results);
+            let mut dispatch = Dispatch {
+                shards,
+                seen,
+                latest,
+                audit_log,
+                queues,
+                outcomes,
+                report,
+                batches: vec![Vec::new(); shards.len()],
+                summary: Summary::default(),
+                rows: VecDeque::new(),
+                next: 0,
+                in_flight: 0,
+            };
+            let result = read_rows(reader, reorder_window, |record, transaction| {
+                dispatch.row(record, transaction, strict)
+            })
+            .and_then(|()| dispatch.finish());
+            let summary = dispatch.summary;
+            // Closing the queues lets the shards' threads finish
+            drop(dispatch);
+            result.map(|()| summary)
+        })
+    }
+
+    /// Brings every shard's changes back into the store
+    pub fn finish(self, store: &mut Store) -> io::Result<()> {
+        let threads = self.shards.len();
+        for (index, shard) in self.shards.into_iter().enumerate() {
+            let shard = shard.into_inner().unwrap_or_else(|err| err.into_inner());
+            store.absorb(shard, |client| shard_of(client, threads) == index)?;
+        }
+        store.extend_audit_log(self.audit_log);
+        Ok(())
+    }
+}
+
+/// The shard a client's rows go to
+pub fn shard_of(client: ClientId, threads: usize) -> usize {
+    usize::from(client.0) % threads
+}
+
+/// Splits a store's accounts and transactions into at least one shard, along with the
+/// shard that saw each transaction id
+///
+/// A store with a [dispute expiry](Store::with_dispute_expiry) is refused, as its disputes
+/// expire for every client at once.
+pub fn split(store: &Store, threads: usize) -> io::Result<(Vec<Mutex<Store>>, Seen)> {
+    if store.get_dispute_expiry().is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "a store with a dispute expiry can't be split into shards",
+        ));
+    }
+    let threads = threads.max(1);
+    let shards = (0..threads)
+        .map(|shard| {
+            let shard = store.shard(|client| shard_of(client, threads) == shard)?;
+            Ok(Mutex::new(shard))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    let seen = store
+        .get_transactions()
+        .records()?
+        .iter()
+        .map(|record| {
+            let transaction = &record.transaction;
+            (transaction.tx, shard_of(transaction.client, threads))
+        })
+        .collect();
+    Ok((shards, seen))
+}
+
+/// Applies each batch of rows handed to a shard, sending back their outcomes
+fn work(
+    shard: &Mutex<Store>,
+    batches: Receiver<Batch>,
+    results: mpsc::Sender<Vec<(u64, Outcome)>>,
+) {
+    for batch in batches {
+        let mut store = shard.lock().unwrap_or_else(|err| err.into_inner());
+        let outcomes = batch
+            .into_iter()
+            .map(|(row, transaction)| (row, apply(&mut store, transaction)))
+            .collect();
+        drop(store);
+        if results.send(outcomes).is_err() {
+            break;
+        }
+    }
+}
+
+/// Applies a transaction, picking out the audit entries it made
+fn apply(store: &mut Store, transaction: Transaction) -> Outcome {
+    let before = store.get_audit_log().len();
+    let result = store.apply_transaction(transaction).map_err(|err| err.1);
+    Outcome::Applied(result, store.get_audit_log()[before..].to_vec())
+}
+
+/// Hands the rows of one input to the shards, and reports their outcomes in order
+struct Dispatch<'p, 'r, W: io::Write> {
+    shards: &'p [Mutex<Store>],
+    seen: &'p mut Seen,
+    latest: &'p mut Option<DateTime<Utc>>,
+    audit_log: &'p mut Vec<AuditEntry>,
+    queues: Vec<SyncSender<Batch>>,
+    outcomes: Receiver<Vec<(u64, Outcome)>>,
+    report: &'r mut ErrorReport<W>,
+    /// The rows waiting to be handed to each shard
+    batches: Vec<Batch>,
+    summary: Summary,
+    /// The rows that haven't been reported yet, in order, with their outcomes once known
+    rows: VecDeque<(StringRecord, Option<Outcome>)>,
+    /// The number of the first row in `rows`
+    next: u64,
+    /// How many batches have been handed to a shard without their outcomes coming back
+    in_flight: usize,
+}
+
+impl<W: io::Write> Dispatch<'_, '_, W> {
+    fn row(
+        &mut self,
+        record: &StringRecord,
+        transaction: Result<Transaction, ParseError>,
+        strict: bool,
+    ) -> Result<(), SpreadsheetError> {
+        let row = self.summary.rows;
+        self.summary.rows += 1;
+        let transaction = match transaction {
+            Ok(transaction) => transaction,
+            Err(err) if strict => {
+                self.wait_for_all()?;
+                self.report.report(&ErrorRecord::malformed(record, &err))?;
+                return Err(err.into());
+            }
+            Err(err) => {
+                self.rows
+                    .push_back((record.clone(), Some(Outcome::Malformed(err))));
+                return Ok(self.report_ready()?);
+            }
+        };
+        self.rows.push_back((record.clone(), None));
+
+        let threads = self.queues.len();
+        let shard = shard_of(transaction.client, threads);
+        let mut alone = true;
+        if let Some(timestamp) = transaction.timestamp {
+            match *self.latest {
+                Some(latest) if timestamp < latest => alone = false,
+                _ => *self.latest = Some(timestamp),
+            }
+        }
+        let seen = transaction
+            .transaction_type
+            .has_amount()
+            .then(|| *self.seen.entry(transaction.tx).or_insert(shard));
+        if seen.is_some_and(|seen| seen != shard) {
+            self.seen.insert(transaction.tx, SHARED);
+            alone = false;
+        }
+        let destination = transaction
+            .destination
+            .map(|destination| (destination, shard_of(destination, threads)))
+            .filter(|(_, destination_shard)| *destination_shard != shard);
+        if destination.is_some() {
+            alone = false;
+        }
+
+        if alone {
+            self.batches[shard].push((row, transaction));
+            if self.batches[shard].len() >= BATCH_SIZE {
+                self.send(shard)?;
+            }
+            return Ok(());
+        }
+
+        self.wait_for_all()?;
+        let outcome = self.apply_across(shard, transaction, destination)?;
+        self.rows[(row - self.next) as usize].1 = Some(outcome);
+        Ok(self.report_ready()?)
+    }
+
+    /// Hands a shard its waiting rows, reporting outcomes while the shard's queue is full
+    fn send(&mut self, shard: usize) -> io::Result<()> {
+        let mut batch = std::mem::take(&mut self.batches[shard]);
+        loop {
+            match self.queues[shard].try_send(batch) {
+                Ok(()) => {
+                    self.in_flight += 1;
+                    return Ok(());
+                }
+                Err(mpsc::TrySendError::Full(returned)) => {
+                    batch = returned;
+                    self.receive()?;
+                    self.report_ready()?;
+                }
+                Err(mpsc::TrySendError::Disconnected(_)) => return Err(shard_stopped()),
+            }
+        }
+    }
+
+    /// Waits for the outcomes of the next batch any shard finishes
+    fn receive(&mut self) -> io::Result<()> {
+        let outcomes = self.outcomes.recv().map_err(|_| shard_stopped())?;
+        self.in_flight -= 1;
+        for (row, outcome) in outcomes {
+            self.rows[(row - self.next) as usize].1 = Some(outcome);
+        }
+        Ok(())
+    }
+
+    /// Waits for every row to be applied, and reports them all
+    fn wait_for_all(&mut self) -> io::Result<()> {
+        for shard in 0..self.batches.len() {
+            if !self.batches[shard].is_empty() {
+                self.send(shard)?;
+            }
+        }
+        while self.in_flight > 0 {
+            self.receive()?;
+        }
+        self.report_ready()
+    }
+
+    /// Applies a row that depends on more than one shard, once every shard is idle
+    fn apply_across(
+        &mut self,
+        shard: usize,
+        transaction: Transaction,
+        destination: Option<(ClientId, usize)>,
+    ) -> io::Result<Outcome> {
+        let shards = self.shards;
+        let lock = |index: usize| shards[index].lock().unwrap_or_else(|err| err.into_inner());
+        let mut store = lock(shard);
+
+        let latest = (0..shards.len())
+            .filter(|index| *index != shard)
+            .filter_map(|index| lock(index).get_latest())
+            .max();
+        if let Some(latest) = latest {
+            store.advance_to(latest);
+        }
+        if self.seen.get(&transaction.tx) == Some(&SHARED) {
+            for index in (0..shards.len()).filter(|index| *index != shard) {
+                if let Some(record) = lock(index).get_transactions().get(transaction.tx)? {
+                    store.share_transaction(&record)?;
+                }
+            }
+        }
+
+        let (destination, destination_shard) = match destination {
+            Some(destination) => destination,
+            None => return Ok(apply(&mut store, transaction)),
+        };
+        let mut destination_store = lock(destination_shard);
+        if let Some(account) = destination_store.get_account(destination)? {
+            store.share_account(&account, None)?;
+        }
+        let timestamp = transaction.timestamp;
+        let outcome = apply(&mut store, transaction);
+        if let Outcome::Applied(Ok(()), _) = outcome {
+            if let Some(account) = store.get_account(destination)? {
+                destination_store.share_account(&account, timestamp)?;
+            }
+        }
+        Ok(outcome)
+    }
+
+    /// Reports every outcome that the rows before it have been reported for
+    fn report_ready(&mut self) -> io::Result<()> {
+        while self
+            .rows
+            .front()
+            .is_some_and(|(_, outcome)| outcome.is_some())
+        {
+            let (record, outcome) = match self.rows.pop_front() {
+                Some((record, Some(outcome))) => (record, outcome),
+                _ => break,
+            };
+            self.next += 1;
+            match outcome {
+                Outcome::Malformed(err) => {
+                    self.summary.malformed += 1;
+                    self.report.report(&ErrorRecord::malformed(&record, &err))?;
+                }
+                Outcome::Applied(result, audit_log) => {
+                    match result {
+                        Ok(()) => self.summary.applied += 1,
+                        Err(err) => {
+                            self.summary.rejected += 1;
+                            self.report.report(&ErrorRecord::rejected(&record, &err))?;
+                        }
+                    }
+                    self.audit_log.extend(audit_log);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for the last rows once reading is done
+    fn finish(&mut self) -> Result<(), SpreadsheetError> {
+        Ok(self.wait_for_all()?)
+    }
+}
+
+fn shard_stopped() -> io::Error {
+    io::Error::other("a shard stopped before its rows were applied")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fmt::Write as _, io};
+
+    use chrono::{DateTime, Duration};
+    use csv::{ReaderBuilder, Writer};
+
+    use crate::{
+        model::decimal_format::DecimalFormat,
+        report::{ErrorReport, ReportFormat},
+        spreadsheet::{read_spreadsheet, write_spreadsheet, Summary},
+        store::Store,
+    };
+
+    use super::Pipeline;
+
+    /// The balances, error report and audit log after applying the input on `threads` threads
+    fn run(data: &str, threads: usize) -> (Summary, String, String, usize) {
+        let mut store = Store::new();
+        let mut reader = ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(data.as_bytes());
+        let mut report = ErrorReport::new(vec![], ReportFormat::Csv).unwrap();
+        let summary = if threads > 1 {
+            let mut pipeline = Pipeline::new(&store, threads).unwrap();
+            let summary = pipeline.read(&mut reader, &mut report, false, 0).unwrap();
+            pipeline.finish(&mut store).unwrap();
+            summary
+        } else {
+            read_spreadsheet(&mut store, &mut reader, &mut report, false, 0).unwrap()
+        };
+        let mut writer = Writer::from_writer(vec![]);
+        write_spreadsheet(&store, &mut writer, &DecimalFormat::default()).unwrap();
+        let balances = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        let report = String::from_utf8(report.into_inner().unwrap()).unwrap();
+        (summary, balances, report, store.get_audit_log().len())
+    }
+
+    #[test]
+    fn matches_a_single_thread() {
+        let data = "\
+type,       client, tx, amount, destination, reason, timestamp
+deposit,    1,      1,  10,     ,            ,       2024-05-01T00:00:00Z
+deposit,    2,      2,  5,      ,            ,       2024-05-01T00:00:01Z
+deposit,    2,      1,  5,      ,            ,       2024-05-01T00:00:02Z
+transfer,   1,      3,  4,      2,           ,       2024-05-01T00:00:03Z
+withdraw,   2,      4,  8,      ,            ,       2024-05-01T00:00:04Z
+deposit,    3,      5,  1,      ,            ,       2024-05-01T00:00:02Z
+dispute,    2,      1,  ,       ,            ,
+dispute,    1,      1,  ,       ,            ,
+chargeback, 1,      1,  ,       ,            ,
+transfer,   2,      6,  1,      1,           ,
+freeze,     2,      7,  ,       ,            audit,
+unlock,     1,      8,  ,       ,            audit,
+deposit,    4,      9,  oops,   ,            ,
+transfer,   4,      10, 1,      3,           ,
+";
+        let sequential = run(data, 1);
+        assert_eq!(sequential.0.applied, 8);
+        assert_eq!(sequential.3, 3);
+        for threads in 2..=4 {
+            assert_eq!(run(data, threads), sequential);
+        }
+    }
+
+    /// A pseudo-random input, with transfers between clients of different shards, reused
+    /// transaction ids, and timestamps that now and then go back in time or are left out
+    fn input(seed: u64, rows: u32) -> String {
+        let mut state = seed * 31 + 7;
+        let mut next = move |bound: u32| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 33) as u32 % bound
+        };
+        let start = DateTime::parse_from_rfc3339("2024-05-01T00:00:00Z").unwrap();
+        let mut data = "type,client,tx,amount,destination,reason,timestamp\n".to_string();
+        let mut time: i64 = 0;
+        for _ in 0..rows {
+            let (client, tx, amount) = (next(8) + 1, next(rows / 2) + 1, next(20) + 1);
+            let (transaction_type, amount, destination) = match next(10) {
+                0..=2 => ("deposit", amount.to_string(), String::new()),
+                3 | 4 => ("withdraw", amount.to_string(), String::new()),
+                5 | 6 => ("transfer", amount.to_string(), (next(8) + 1).to_string()),
+                7 => ("dispute", String::new(), String::new()),
+                8 => (
+                    ["resolve", "chargeback"][next(2) as usize],
+                    String::new(),
+                    String::new(),
+                ),
+                _ => (
+                    ["freeze", "unlock"][next(2) as usize],
+                    String::new(),
+                    String::new(),
+                ),
+            };
+            let reason = if destination.is_empty() && amount.is_empty() {
+                "audit"
+            } else {
+                ""
+            };
+            let timestamp = match next(10) {
+                0 => String::new(),
+                1 => (start + Duration::seconds(time - 5)).to_rfc3339(),
+                _ => {
+                    time += i64::from(next(3));
+                    (start + Duration::seconds(time)).to_rfc3339()
+                }
+            };
+            writeln!(
+                data,
+                "{},{},{},{},{},{},{}",
+                transaction_type, client, tx, amount, destination, reason, timestamp
+            )
+            .unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn matches_a_single_thread_on_random_inputs() {
+        for seed in 0..10 {
+            let data = input(seed, 400);
+            let sequential = run(&data, 1);
+            assert!(sequential.0.applied > 0 && sequential.0.rejected > 0);
+            for threads in 2..=4 {
+                assert_eq!(run(&data, threads), sequential, "seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn refuses_a_store_with_a_dispute_expiry() {
+        let store = Store::new().with_dispute_expiry(Duration::days(30));
+        let err = Pipeline::new(&store, 2).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,218 @@
+//! Reports of rows that could not be applied
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use clap::ValueEnum;
+use csv::StringRecord;
+use serde::Serialize;
+
+use crate::model::error::{parse_error::ParseError, transaction_error::TransactionError};
+
+/// How rejected rows are written
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    /// Human readable messages
+    Text,
+    /// One csv row per rejection, with the error's fields in a `key=value;...` column
+    Csv,
+    /// One json object per line
+    Jsonl,
+}
+
+/// A single rejected row
+#[derive(Debug, Serialize)]
+pub struct ErrorRecord {
+    /// Line of the input the row started on
+    pub line: u64,
+    /// Byte offset of the row in the input
+    pub byte: u64,
+    /// The row's fields, joined back together with commas
+    pub row: String,
+    /// Name of the error variant, such as `Overdraft`, or `ParseError` for malformed rows
+    pub error: &'static str,
+    pub message: String,
+    /// Data carried by the error, such as `available` for an `Overdraft`
+    pub fields: BTreeMap<&'static str, String>,
+}
+
+impl ErrorRecord {
+    /// A row that was read, but refused by the [Store](crate::store::Store)
+    pub fn rejected(record: &StringRecord, err: &TransactionError) -> ErrorRecord {
+        let position = record.position();
+        ErrorRecord {
+            line: position.map_or(0, |position| position.line()),
+            byte: position.map_or(0, |position| position.byte()),
+            row: join(record),
+            error: err.name(),
+            message: err.to_string(),
+            fields: err.fields().into_iter().collect(),
+        }
+    }
+
+    /// A row that could not be read as a transaction
+    pub fn malformed(record: &StringRecord, err: &ParseError) -> ErrorRecord {
+        ErrorRecord {
+            line: err.line,
+            byte: err.byte,
+            row: join(record),
+            error: "ParseError",
+            message: err.message.clone(),
+            fields: BTreeMap::new(),
+        }
+    }
+}
+
+fn join(record: &StringRecord) -> String {
+    record.iter().collect::<Vec<_>>().join(",")
+}
+
+enum Sink<W: Write> {
+    Text(W),
+    Csv(Box<csv::Writer<W>>),
+    Jsonl(W),
+}
+
+/// An [ErrorRecord] along with the name of the input it came from
+#[derive(Serialize)]
+struct SourcedRecord<'a> {
+    source: &'a str,
+    #[serde(flatten)]
+    record: &'a ErrorRecord,
+}
+
+/// Writes [ErrorRecord]s in a chosen [ReportFormat]
+///
+/// Each record is labelled with the current source, see [set_source](ErrorReport::set_source).
+pub struct ErrorReport<W: Write> {
+    sink: Sink<W>,
+    source: String,
+}
+
+impl<W: Write> ErrorReport<W> {
+    /// Creates a report, writing the header row straight away if the format has one
+    pub fn new(writer: W, format: ReportFormat) -> io::Result<ErrorReport<W>> {
+        let sink = match format {
+            ReportFormat::Text => Sink::Text(writer),
+            ReportFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(writer);
+                writer.write_record([
+                    "source", "line", "byte", "row", "error", "message", "fields",
+                ])?;
+                Sink::Csv(Box::new(writer))
+            }
+            ReportFormat::Jsonl => Sink::Jsonl(writer),
+        };
+        Ok(ErrorReport {
+            sink,
+            source: String::new(),
+        })
+    }
+
+    /// Sets the name of the input that following records come from, such as a file name
+    pub fn set_source<S: Into<String>>(&mut self, source: S) {
+        self.source = source.into();
+    }
+
+    pub fn report(&mut self, record: &ErrorRecord) -> io::Result<()> {
+        match &mut self.sink {
+            Sink::Text(writer) => writeln!(
+                writer,
+                "\nError in {} on line {}: {}\n{}",
+                self.source, record.line, record.message, record.row
+            ),
+            Sink::Csv(writer) => {
+                let fields = record
+                    .fields
+                    .iter()
+                    .map(|(field, value)| format!("{}={}", field, value))
+                    .collect::<Vec<_>>()
+                    .join(";");
+                writer.write_record([
+                    &self.source,
+                    &record.line.to_string(),
+                    &record.byte.to_string(),
+                    &record.row,
+                    record.error,
+                    &record.message,
+                    &fields,
+                ])?;
+                Ok(())
+            }
+            Sink::Jsonl(writer) => {
+                let record = SourcedRecord {
+                    source: &self.source,
+                    record,
+                };
+                serde_json::to_writer(&mut *writer, &record)?;
+                writeln!(writer)
+            }
+        }
+    }
+
+    /// Flushes and returns the underlying writer
+    pub fn into_inner(self) -> io::Result<W> {
+        match self.sink {
+            Sink::Text(mut writer) | Sink::Jsonl(mut writer) => {
+                writer.flush()?;
+                Ok(writer)
+            }
+            Sink::Csv(writer) => writer
+                .into_inner()
+                .map_err(|err| io::Error::new(err.error().kind(), err.error().to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use csv::{Position, StringRecord};
+    use rust_decimal::Decimal;
+
+    use crate::model::error::transaction_error::TransactionError;
+
+    use super::{ErrorRecord, ErrorReport, ReportFormat};
+
+    fn overdraft() -> ErrorRecord {
+        let mut record = StringRecord::from(vec!["withdraw", "2", "5", "3.0"]);
+        let mut position = Position::new();
+        position.set_line(6).set_byte(120);
+        record.set_position(Some(position));
+        let err = TransactionError::Overdraft {
+            available: Decimal::from(2),
+            transaction_amount: "3.0".parse().unwrap(),
+            limit: Decimal::from(0),
+        };
+        ErrorRecord::rejected(&record, &err)
+    }
+
+    fn write(format: ReportFormat) -> String {
+        let mut report = ErrorReport::new(vec![], format).unwrap();
+        report.set_source("input.csv");
+        report.report(&overdraft()).unwrap();
+        String::from_utf8(report.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn csv_report() {
+        assert_eq!(
+            write(ReportFormat::Csv),
+            "source,line,byte,row,error,message,fields\n\
+            input.csv,6,120,\"withdraw,2,5,3.0\",Overdraft,\
+            Tried to withdraw 3.0 from an available balance of 2 with an overdraft limit of 0,\
+            available=2;limit=0;transaction_amount=3.0\n"
+        );
+    }
+
+    #[test]
+    fn jsonl_report() {
+        assert_eq!(
+            write(ReportFormat::Jsonl),
+            "{\"source\":\"input.csv\",\"line\":6,\"byte\":120,\"row\":\"withdraw,2,5,3.0\",\"error\":\"Overdraft\",\
+            \"message\":\"Tried to withdraw 3.0 from an available balance of 2 with an overdraft limit of 0\",\
+            \"fields\":{\"available\":\"2\",\"limit\":\"0\",\"transaction_amount\":\"3.0\"}}\n"
+        );
+    }
+}
@@ -0,0 +1,185 @@
+//! Saving the [Store] between runs, so that each run only processes new transactions
+//!
+//! A snapshot file is a header line followed by the [StoreSnapshot] as json:
+//! ```text
+//! pseudo-bank-snapshot 2 5d1e0a3c
+//! {"accounts":[...],"transactions":[...],...}
+//! ```
+//! The header holds the format version and a crc32 checksum of the json, in hex.
+//! Snapshots of another version, or whose checksum doesn't match, are refused.
+
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    model::{
+        account::Account,
+        error::snapshot_error::SnapshotError,
+        id::{client_id::ClientId, transaction_id::TransactionId},
+        transaction_record::TransactionRecord,
+    },
+    store::Store,
+};
+
+/// The first word of every snapshot file
+const MAGIC: &str = "pseudo-bank-snapshot";
+
+/// The version of the snapshot format written, and the only one that can be loaded
+///
+/// Version 2 added how far the journal had got, which version 1 snapshots can't tell.
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+/// Everything a [Store] needs to carry on where it left off
+///
+/// Policies and settings aren't included, they come from the options of each run.
+/// Neither are the audit log or balances over time, which only cover the run they were kept in.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoreSnapshot {
+    pub accounts: Vec<Account>,
+    /// Every stored transaction and its dispute history, by transaction id
+    pub transactions: Vec<TransactionRecord>,
+    /// The latest timestamp applied, which later runs must not go back before
+    pub latest: Option<DateTime<Utc>>,
+    /// Open disputes that expire, with their client and deadline
+    pub dispute_deadlines: Vec<(TransactionId, ClientId, DateTime<Utc>)>,
+    /// How many transactions had been [journaled](crate::journal) by the time of the snapshot
+    pub journaled: u64,
+}
+
+/// Writes a snapshot of the store in the snapshot file format
+pub fn write_snapshot<W: Write>(store: &Store, mut writer: W) -> Result<(), SnapshotError> {
+    let body = serde_json::to_vec(&store.snapshot()?)?;
+    let checksum = crc32fast::hash(&body);
+    writeln!(writer, "{} {} {:08x}", MAGIC, SNAPSHOT_VERSION, checksum)?;
+    writer.write_all(&body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads a snapshot file, checking its version and checksum
+pub fn read_snapshot<R: Read>(mut reader: R) -> Result<StoreSnapshot, SnapshotError> {
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents)?;
+    let newline = contents
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .ok_or(SnapshotError::NotASnapshot)?;
+    let (header, body) = (&contents[..newline], &contents[newline + 1..]);
+
+    let header = std::str::from_utf8(header).map_err(|_| SnapshotError::NotASnapshot)?;
+    let (version, checksum) = match header.split(' ').collect::<Vec<_>>()[..] {
+        [MAGIC, version, checksum] => (version, checksum),
+        _ => return Err(SnapshotError::NotASnapshot),
+    };
+    if version != SNAPSHOT_VERSION.to_string() {
+        return Err(SnapshotError::UnsupportedVersion {
+            found: version.to_string(),
+            expected: SNAPSHOT_VERSION,
+        });
+    }
+    let expected = u32::from_str_radix(checksum, 16).map_err(|_| SnapshotError::NotASnapshot)?;
+    let found = crc32fast::hash(body);
+    if found != expected {
+        return Err(SnapshotError::ChecksumMismatch { expected, found });
+    }
+    Ok(serde_json::from_slice(body)?)
+}
+
+/// Saves a snapshot of the store to a file
+///
+/// The snapshot is written next to the file first and then moved over it, so an existing
+/// snapshot is never left half written.
+pub fn save_snapshot(store: &Store, path: &Path) -> Result<(), SnapshotError> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let file = File::create(&temporary)?;
+    write_snapshot(store, &file)?;
+    file.sync_all()?;
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// Loads a snapshot from a file into a new store
+pub fn load_snapshot(store: Store, path: &Path) -> Result<Store, SnapshotError> {
+    let snapshot = read_snapshot(File::open(path)?)?;
+    Ok(store.restore(snapshot)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        model::{
+            error::snapshot_error::SnapshotError, id::client_id::ClientId,
+            transaction_type::TransactionType,
+        },
+        store::Store,
+        test_util::transaction,
+    };
+
+    use super::{read_snapshot, write_snapshot};
+
+    #[test]
+    fn restores_accounts_disputes_and_history() {
+        let mut store = Store::new();
+        for transaction in [
+            transaction(TransactionType::Deposit, 1, Some(10)),
+            transaction(TransactionType::Deposit, 2, Some(5)),
+            transaction(TransactionType::Dispute, 2, None),
+        ] {
+            store.apply_transaction(transaction).unwrap();
+        }
+        let mut bytes = Vec::new();
+        write_snapshot(&store, &mut bytes).unwrap();
+
+        let mut store = Store::new()
+            .restore(read_snapshot(bytes.as_slice()).unwrap())
+            .unwrap();
+        let account = store.get_account(ClientId(1)).unwrap().unwrap();
+        assert_eq!(account.get_available(), 10.into());
+        assert_eq!(account.get_held(), 5.into());
+
+        assert!(store
+            .apply_transaction(transaction(TransactionType::Deposit, 1, Some(1)))
+            .is_err());
+        assert!(store
+            .apply_transaction(transaction(TransactionType::Chargeback, 2, None))
+            .is_ok());
+        let account = store.get_account(ClientId(1)).unwrap().unwrap();
+        assert_eq!(account.get_total(), 10.into());
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn refuses_corrupt_and_incompatible_snapshots() {
+        let mut store = Store::new();
+        store
+            .apply_transaction(transaction(TransactionType::Deposit, 1, Some(10)))
+            .unwrap();
+        let mut bytes = Vec::new();
+        write_snapshot(&store, &mut bytes).unwrap();
+        let snapshot = String::from_utf8(bytes).unwrap();
+
+        let corrupt = snapshot.replace("\"10\"", "\"99\"");
+        assert!(matches!(
+            read_snapshot(corrupt.as_bytes()),
+            Err(SnapshotError::ChecksumMismatch { .. })
+        ));
+        for version in [" 1 ", " 3 "] {
+            let other = snapshot.replacen(" 2 ", version, 1);
+            assert!(matches!(
+                read_snapshot(other.as_bytes()),
+                Err(SnapshotError::UnsupportedVersion { .. })
+            ));
+        }
+        assert!(matches!(
+            read_snapshot("client,available\n".as_bytes()),
+            Err(SnapshotError::NotASnapshot)
+        ));
+    }
+}
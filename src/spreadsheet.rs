@@ -1,30 +1,324 @@
 //! A couple of functions for processing spreadsheets
 
-use crate::store::Store;
+use std::{collections::BTreeMap, fmt, io, ops::AddAssign};
+
+use clap::ValueEnum;
+use csv::StringRecord;
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+
+use crate::{
+    model::{
+        account::Account,
+        account_summary::AccountSummary,
+        decimal_format::DecimalFormat,
+        error::{
+            parse_error::ParseError, spreadsheet_error::SpreadsheetError,
+            transaction_error::TransactionError,
+        },
+        transaction::Transaction,
+    },
+    report::{ErrorRecord, ErrorReport},
+    store::{InputOutcome, Store},
+};
+
+/// Counts of what happened to each row of a spreadsheet
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub rows: u64,
+    pub applied: u64,
+    /// Rows that were read, but refused by the store
+    pub rejected: u64,
+    /// Rows that could not be read as a transaction
+    pub malformed: u64,
+    /// Rows a restored snapshot already covers, see [InputOutcome::Skipped]
+    pub skipped: u64,
+}
+
+impl AddAssign for Summary {
+    fn add_assign(&mut self, other: Summary) {
+        self.rows += other.rows;
+        self.applied += other.applied;
+        self.rejected += other.rejected;
+        self.malformed += other.malformed;
+        self.skipped += other.skipped;
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Processed {} rows: {} applied, {} rejected, {} malformed",
+            self.rows, self.applied, self.rejected, self.malformed
+        )?;
+        if self.skipped > 0 {
+            write!(f, ", {} skipped", self.skipped)?;
+        }
+        Ok(())
+    }
+}
 
 /// Takes a spreadsheet reader iterator and processes each line accourding to the store
-pub fn read_spreadsheet<T: std::io::Read>(store: &mut Store, reader: &mut csv::Reader<T>) {
-    reader
-        .deserialize()
-        .filter_map(|transaction| transaction.ok())
-        .map(|transaction| store.apply_transaction(transaction))
-        .for_each(|result| {
-            if let Err(err) = result {
-                eprintln!("\nError: {}\n{:?}", err.1, err.0);
+///
+/// Rows rejected by the store, or that could not be parsed, are written to the error report.
+/// In `strict` mode the first malformed row is reported and then stops processing.
+/// Timestamped rows up to `reorder_window` rows out of place are put back in time order,
+/// see [for_each_row].
+pub fn read_spreadsheet<T: io::Read, W: io::Write>(
+    store: &mut Store,
+    reader: &mut csv::Reader<T>,
+    report: &mut ErrorReport<W>,
+    strict: bool,
+    reorder_window: usize,
+) -> Result<Summary, SpreadsheetError> {
+    for_each_row(
+        reader,
+        report,
+        strict,
+        reorder_window,
+        |record, transaction| {
+            let result = match record.position() {
+                Some(position) => store.apply_input(transaction, position.byte()),
+                None => store
+                    .apply_transaction(transaction)
+                    .map(|()| InputOutcome::Applied),
+            };
+            Ok(result.map_err(|err| err.1))
+        },
+    )
+}
+
+/// Checks that every row can be read as a transaction, without applying any of them
+pub fn validate_spreadsheet<T: io::Read, W: io::Write>(
+    reader: &mut csv::Reader<T>,
+    report: &mut ErrorReport<W>,
+    strict: bool,
+) -> Result<Summary, SpreadsheetError> {
+    for_each_row(reader, report, strict, 0, |_, _| {
+        Ok(Ok(InputOutcome::Applied))
+    })
+}
+
+/// Parses each row and hands it to `apply`, keeping count of the outcomes
+///
+/// Malformed rows, and rows `apply` rejects, are written to the error report.
+/// An [io::Error] returned by `apply` stops processing.
+///
+/// Rows are handed over in the order [read_rows] puts them in.
+pub fn for_each_row<T, W, F>(
+    reader: &mut csv::Reader<T>,
+    report: &mut ErrorReport<W>,
+    strict: bool,
+    reorder_window: usize,
+    mut apply: F,
+) -> Result<Summary, SpreadsheetError>
+where
+    T: io::Read,
+    W: io::Write,
+    F: FnMut(&StringRecord, Transaction) -> io::Result<Result<InputOutcome, TransactionError>>,
+{
+    let mut summary = Summary::default();
+    read_rows(reader, reorder_window, |record, transaction| {
+        summary.rows += 1;
+        match transaction {
+            Ok(transaction) => apply_row(&mut apply, report, &mut summary, record, transaction)?,
+            Err(err) => {
+                report.report(&ErrorRecord::malformed(record, &err))?;
+                if strict {
+                    return Err(err.into());
+                }
+                summary.malformed += 1;
             }
-        });
+        }
+        Ok(())
+    })?;
+    Ok(summary)
 }
 
-/// Given a store, will write the current account information to a spreadsheet writer
-pub fn write_spreadsheet<T: std::io::Write>(store: &mut Store, writer: &mut csv::Writer<T>) {
-    store.get_accounts().iter().for_each(|account| {
-        writer.serialize(account.1).unwrap();
+/// Parses each row and hands it to `handle`, along with the row as it was read
+///
+/// Rows that can't be parsed are handed over straight away with their [ParseError].
+/// With a `reorder_window` above zero, up to that many timestamped rows are held back and
+/// handed over earliest first, so rows that arrive a little late are still applied in time
+/// order. A row without a timestamp hands over every row held back before it.
+/// An error returned by `handle` stops reading.
+pub fn read_rows<T, F>(
+    reader: &mut csv::Reader<T>,
+    reorder_window: usize,
+    mut handle: F,
+) -> Result<(), SpreadsheetError>
+where
+    T: io::Read,
+    F: FnMut(&StringRecord, Result<Transaction, ParseError>) -> Result<(), SpreadsheetError>,
+{
+    let headers = reader.headers().map_err(io::Error::from)?.clone();
+    let mut record = StringRecord::new();
+    let mut rows = 0u64;
+    let mut held_back = BTreeMap::new();
+    loop {
+        let transaction = match reader.read_record(&mut record) {
+            Ok(true) => record.deserialize::<Transaction>(Some(&headers)),
+            Ok(false) => break,
+            Err(err) if err.is_io_error() => return Err(io::Error::from(err).into()),
+            Err(err) => Err(err),
+        };
+        rows += 1;
+
+        let transaction = match transaction {
+            Ok(transaction) => transaction,
+            Err(err) => {
+                let err = ParseError::new(&err, &record);
+                handle(&record, Err(err))?;
+                continue;
+            }
+        };
+
+        match transaction.timestamp {
+            Some(timestamp) if reorder_window > 0 => {
+                // The row number keeps rows with the same timestamp in their original order
+                held_back.insert((timestamp, rows), (record.clone(), transaction));
+                if held_back.len() > reorder_window {
+                    if let Some((_, (record, transaction))) = held_back.pop_first() {
+                        handle(&record, Ok(transaction))?;
+                    }
+                }
+            }
+            _ => {
+                while let Some((_, (record, transaction))) = held_back.pop_first() {
+                    handle(&record, Ok(transaction))?;
+                }
+                handle(&record, Ok(transaction))?;
+            }
+        }
+    }
+    while let Some((_, (record, transaction))) = held_back.pop_first() {
+        handle(&record, Ok(transaction))?;
+    }
+    Ok(())
+}
+
+/// Hands a parsed row to `apply`, counting and reporting the outcome
+fn apply_row<W, F>(
+    apply: &mut F,
+    report: &mut ErrorReport<W>,
+    summary: &mut Summary,
+    record: &StringRecord,
+    transaction: Transaction,
+) -> io::Result<()>
+where
+    W: io::Write,
+    F: FnMut(&StringRecord, Transaction) -> io::Result<Result<InputOutcome, TransactionError>>,
+{
+    match apply(record, transaction)? {
+        Ok(InputOutcome::Applied) => summary.applied += 1,
+        Ok(InputOutcome::Skipped) => summary.skipped += 1,
+        Err(err) => {
+            summary.rejected += 1;
+            report.report(&ErrorRecord::rejected(record, &err))?;
+        }
+    }
+    Ok(())
+}
+
+/// What accounts are sorted by for output
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum AccountOrder {
+    Client,
+    Total,
+    Available,
+    Held,
+}
+
+/// Picks the accounts to output and sorts them, from smallest to largest or the other way around
+///
+/// Accounts with equal balances are always kept in client id order.
+pub fn select_accounts(
+    store: &Store,
+    order: AccountOrder,
+    descending: bool,
+    locked_only: bool,
+) -> io::Result<Vec<Account>> {
+    let mut accounts = store
+        .get_accounts()?
+        .values()
+        .filter(|account| !locked_only || account.is_locked())
+        .cloned()
+        .collect::<Vec<_>>();
+    let key = |account: &Account| match order {
+        AccountOrder::Client => Decimal::from(account.get_id().0),
+        AccountOrder::Total => account.get_total(),
+        AccountOrder::Available => account.get_available(),
+        AccountOrder::Held => account.get_held(),
+    };
+    accounts.sort_by(|a, b| {
+        let ordering = key(a).cmp(&key(b));
+        if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
     });
+    Ok(accounts)
+}
+
+/// Summarizes an account for output, with credit columns if the store has client settings
+pub fn summarize(store: &Store, account: &Account, format: &DecimalFormat) -> AccountSummary {
+    let summary = AccountSummary::new(account, format);
+    if store.get_client_settings().is_empty() {
+        summary
+    } else {
+        summary.with_credit(account, format)
+    }
+}
+
+/// Reads a file of settings loaded alongside the transactions, such as
+/// [ClientSettings](crate::model::client_settings::ClientSettings) or
+/// [ExchangeRates](crate::model::exchange_rate::ExchangeRate)
+///
+/// Unlike transactions, a settings row that can't be parsed is always an error.
+pub fn read_settings<T: io::Read, D: DeserializeOwned>(
+    reader: &mut csv::Reader<T>,
+) -> Result<Vec<D>, SpreadsheetError> {
+    let headers = reader.headers().map_err(io::Error::from)?.clone();
+    let mut record = StringRecord::new();
+    let mut rows = Vec::new();
+    loop {
+        match reader.read_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => return Ok(rows),
+            Err(err) if err.is_io_error() => return Err(io::Error::from(err).into()),
+            Err(err) => return Err(ParseError::new(&err, &record).into()),
+        }
+        match record.deserialize(Some(&headers)) {
+            Ok(row) => rows.push(row),
+            Err(err) => return Err(ParseError::new(&err, &record).into()),
+        }
+    }
+}
+
+/// Given a store, will write the current account information to a spreadsheet writer
+///
+/// Balances are formatted consistently with each other, see [AccountSummary::new].
+pub fn write_spreadsheet<T: io::Write>(
+    store: &Store,
+    writer: &mut csv::Writer<T>,
+    format: &DecimalFormat,
+) -> csv::Result<()> {
+    for account in store.get_accounts()?.values() {
+        writer.serialize(summarize(store, account, format))?;
+    }
+    writer.flush()?;
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        model::{client_settings::ClientSettings, id::client_id::ClientId},
+        report::ReportFormat,
+    };
     use csv::{ReaderBuilder, Writer};
 
     #[test]
@@ -45,10 +339,223 @@ chargeback, 1,      1,
         let mut reader = ReaderBuilder::new()
             .trim(csv::Trim::All)
             .from_reader(data.as_bytes());
-        read_spreadsheet(&mut store, &mut reader);
+        let mut report = ErrorReport::new(vec![], ReportFormat::Csv).unwrap();
+        let summary = read_spreadsheet(&mut store, &mut reader, &mut report, false, 0).unwrap();
+        let mut writer = Writer::from_writer(vec![]);
+        write_spreadsheet(&store, &mut writer, &DecimalFormat::default()).unwrap();
+        let data = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            data,
+            "client,available,held,total,locked\n\
+            1,0.5000,0.0000,0.5000,true\n\
+            2,2.0000,0.0000,2.0000,false\n"
+        );
+
+        let errors = String::from_utf8(report.into_inner().unwrap()).unwrap();
+        let errors = errors.lines().collect::<Vec<_>>();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[1].starts_with(",6,143,\"withdraw,2,5,3.0\",Overdraft,"));
+        assert_eq!(
+            summary,
+            Summary {
+                rows: 9,
+                applied: 8,
+                rejected: 1,
+                malformed: 0,
+                skipped: 0
+            }
+        );
+    }
+
+    const MALFORMED: &str = "\
+type,       client, tx, amount
+deposit,    1,      1,  1.0
+refund,     1,      2,  1.0
+deposit,    x,      3,  1.0
+deposit,    1,      4,  one
+deposit,    1,      5
+deposit,    1,      6,  2.0
+";
+
+    #[test]
+    fn reports_malformed_rows() {
+        let mut store = Store::new();
+        let mut reader = ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(MALFORMED.as_bytes());
+        let mut report = ErrorReport::new(vec![], ReportFormat::Csv).unwrap();
+        let summary = read_spreadsheet(&mut store, &mut reader, &mut report, false, 0).unwrap();
+        assert_eq!(
+            summary,
+            Summary {
+                rows: 6,
+                applied: 2,
+                rejected: 0,
+                malformed: 4,
+                skipped: 0
+            }
+        );
+
+        let errors = String::from_utf8(report.into_inner().unwrap()).unwrap();
+        let lines = errors
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').nth(1).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec!["3", "4", "5", "6"]);
+        assert!(errors
+            .lines()
+            .all(|line| line.contains("ParseError") || line.starts_with("source")));
+    }
+
+    #[test]
+    fn strict_mode_stops_at_malformed_row() {
+        let mut store = Store::new();
+        let mut reader = ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(MALFORMED.as_bytes());
+        let mut report = ErrorReport::new(vec![], ReportFormat::Csv).unwrap();
+        let result = read_spreadsheet(&mut store, &mut reader, &mut report, true, 0);
+        assert!(matches!(
+            result,
+            Err(SpreadsheetError::Malformed(ParseError { line: 3, .. }))
+        ));
+        assert_eq!(store.get_accounts().unwrap().len(), 1);
+        let errors = String::from_utf8(report.into_inner().unwrap()).unwrap();
+        assert_eq!(errors.lines().count(), 2);
+    }
+
+    #[test]
+    fn validates_without_applying() {
+        let mut reader = ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(MALFORMED.as_bytes());
+        let mut report = ErrorReport::new(vec![], ReportFormat::Csv).unwrap();
+        let summary = validate_spreadsheet(&mut reader, &mut report, false).unwrap();
+        assert_eq!(summary.applied, 2);
+        assert_eq!(summary.malformed, 4);
+    }
+
+    #[test]
+    fn selects_and_sorts_accounts() {
+        let data = "\
+type,       client, tx, amount
+deposit,    3,      1,  5.0
+deposit,    1,      2,  9.0
+deposit,    2,      3,  1.0
+deposit,    4,      4,  5.0
+dispute,    1,      2,
+chargeback, 1,      2,
+dispute,    4,      4,
+";
+        let mut store = Store::new();
+        let mut reader = ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(data.as_bytes());
+        let mut report = ErrorReport::new(vec![], ReportFormat::Csv).unwrap();
+        read_spreadsheet(&mut store, &mut reader, &mut report, false, 0).unwrap();
+
+        let clients = |order, locked_only| {
+            select_accounts(&store, order, false, locked_only)
+                .unwrap()
+                .iter()
+                .map(|account| account.get_id().0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(clients(AccountOrder::Client, false), vec![1, 2, 3, 4]);
+        assert_eq!(clients(AccountOrder::Total, false), vec![1, 2, 3, 4]);
+        assert_eq!(clients(AccountOrder::Available, false), vec![1, 4, 2, 3]);
+        assert_eq!(clients(AccountOrder::Held, false), vec![1, 2, 3, 4]);
+        assert_eq!(clients(AccountOrder::Client, true), vec![1]);
+
+        let descending = select_accounts(&store, AccountOrder::Available, true, false)
+            .unwrap()
+            .iter()
+            .map(|account| account.get_id().0)
+            .collect::<Vec<_>>();
+        assert_eq!(descending, vec![3, 2, 1, 4]);
+    }
+
+    #[test]
+    fn refuses_negative_overdraft_limits() {
+        let settings = "\
+client, overdraft_limit
+1,      -5.0
+";
+        let mut reader = ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(settings.as_bytes());
+        let settings: Result<Vec<ClientSettings>, _> = read_settings(&mut reader);
+        assert!(matches!(settings, Err(SpreadsheetError::Malformed(_))));
+    }
+
+    #[test]
+    fn applies_overdraft_limits_from_client_settings() {
+        let settings = "\
+client, overdraft_limit
+1,      5.0
+";
+        let mut reader = ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(settings.as_bytes());
+        let settings: Vec<ClientSettings> = read_settings(&mut reader).unwrap();
+        let mut store = Store::new().with_client_settings(settings);
+
+        let data = "\
+type,       client, tx, amount
+deposit,    1,      1,  1.0
+withdraw,   1,      2,  4.0
+withdraw,   1,      3,  3.0
+deposit,    2,      4,  1.0
+withdraw,   2,      5,  2.0
+";
+        let mut reader = ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(data.as_bytes());
+        let mut report = ErrorReport::new(vec![], ReportFormat::Csv).unwrap();
+        let summary = read_spreadsheet(&mut store, &mut reader, &mut report, false, 0).unwrap();
+        assert_eq!(summary.rejected, 2);
+
         let mut writer = Writer::from_writer(vec![]);
-        write_spreadsheet(&mut store, &mut writer);
+        write_spreadsheet(&store, &mut writer, &DecimalFormat::default()).unwrap();
         let data = String::from_utf8(writer.into_inner().unwrap()).unwrap();
-        println!("{}", data);
+        assert_eq!(
+            data,
+            "client,available,held,total,locked,credit_limit,credit_used\n\
+            1,-3.0000,0.0000,-3.0000,false,5.0000,3.0000\n\
+            2,1.0000,0.0000,1.0000,false,0.0000,0.0000\n"
+        );
+
+        let errors = String::from_utf8(report.into_inner().unwrap()).unwrap();
+        assert!(errors.contains("available=-3;limit=5;transaction_amount=3\n"));
+    }
+
+    #[test]
+    fn reorders_rows_within_the_window() {
+        let data = "\
+type,    client, tx, amount, timestamp
+deposit, 1,      1,  1.0,    2024-05-01T09:00:00Z
+deposit, 1,      2,  2.0,    2024-05-01T11:00:00Z
+deposit, 1,      3,  4.0,    2024-05-01T10:00:00Z
+deposit, 1,      4,  8.0,    2024-05-01T09:30:00Z
+";
+        let read = |reorder_window| {
+            let mut store = Store::new();
+            let mut reader = ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(data.as_bytes());
+            let mut report = ErrorReport::new(vec![], ReportFormat::Csv).unwrap();
+            let summary =
+                read_spreadsheet(&mut store, &mut reader, &mut report, false, reorder_window)
+                    .unwrap();
+            (
+                summary.rejected,
+                store.get_accounts().unwrap()[&ClientId(1)].get_available(),
+            )
+        };
+
+        assert_eq!(read(0), (2, "3".parse().unwrap()));
+        assert_eq!(read(1), (1, "7".parse().unwrap()));
+        assert_eq!(read(2), (0, "15".parse().unwrap()));
     }
 }
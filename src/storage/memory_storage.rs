@@ -0,0 +1,74 @@
+use std::{borrow::Cow, collections::BTreeMap, io};
+
+use crate::{
+    history::{memory_history::MemoryHistory, TransactionHistory},
+    model::{
+        account::Account,
+        id::{client_id::ClientId, transaction_id::TransactionId},
+        transaction_record::TransactionRecord,
+    },
+};
+
+use super::Storage;
+
+/// Keeps every account in a [BTreeMap], and transactions in a [TransactionHistory]
+///
+/// There are at most as many accounts as client ids, so they always fit in memory.
+/// The history decides whether transactions do.
+#[derive(Debug)]
+pub struct MemoryStorage {
+    accounts: BTreeMap<ClientId, Account>,
+    history: Box<dyn TransactionHistory>,
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        MemoryStorage::new(Box::new(MemoryHistory::new()))
+    }
+}
+
+impl MemoryStorage {
+    pub fn new(history: Box<dyn TransactionHistory>) -> MemoryStorage {
+        MemoryStorage {
+            accounts: BTreeMap::new(),
+            history,
+        }
+    }
+}
+
+impl TransactionHistory for MemoryStorage {
+    fn get(&self, tx: TransactionId) -> io::Result<Option<TransactionRecord>> {
+        self.history.get(tx)
+    }
+
+    fn insert(&mut self, record: &TransactionRecord) -> io::Result<()> {
+        self.history.insert(record)
+    }
+
+    fn records(&self) -> io::Result<Vec<TransactionRecord>> {
+        self.history.records()
+    }
+
+    fn into_records(self: Box<Self>) -> io::Result<Vec<TransactionRecord>> {
+        self.history.into_records()
+    }
+
+    fn len(&self) -> usize {
+        self.history.len()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get_account(&self, client: ClientId) -> io::Result<Option<Cow<'_, Account>>> {
+        Ok(self.accounts.get(&client).map(Cow::Borrowed))
+    }
+
+    fn insert_account(&mut self, account: Account) -> io::Result<()> {
+        self.accounts.insert(account.get_id(), account);
+        Ok(())
+    }
+
+    fn accounts(&self) -> io::Result<Cow<'_, BTreeMap<ClientId, Account>>> {
+        Ok(Cow::Borrowed(&self.accounts))
+    }
+}
@@ -0,0 +1,49 @@
+//! Storage for accounts and processed transactions
+//!
+//! The [Store](crate::store::Store) looks accounts up and stores them back every time one
+//! changes, and keeps transactions in the [TransactionHistory] the storage extends.
+//! [MemoryStorage](memory_storage::MemoryStorage) keeps accounts in memory along with any
+//! [TransactionHistory]. With the `sqlite` feature,
+//! [SqliteStorage](sqlite_storage::SqliteStorage) keeps both in a SQLite database file
+//! instead, where they can be queried after a run and carried on from by later runs.
+
+pub mod memory_storage;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_storage;
+
+use std::{borrow::Cow, collections::BTreeMap, io};
+
+use crate::{
+    history::TransactionHistory,
+    model::{account::Account, id::client_id::ClientId, transaction_record::TransactionRecord},
+};
+
+/// A keyed collection of accounts, along with the history of transactions
+pub trait Storage: TransactionHistory {
+    /// Looks up a client's account, borrowed where the storage keeps it in memory
+    fn get_account(&self, client: ClientId) -> io::Result<Option<Cow<'_, Account>>>;
+
+    /// Stores an account, replacing any existing account of the same client
+    fn insert_account(&mut self, account: Account) -> io::Result<()>;
+
+    /// Every stored account, ordered by client id, borrowed where they are kept in memory
+    fn accounts(&self) -> io::Result<Cow<'_, BTreeMap<ClientId, Account>>>;
+
+    /// Stores a transaction's record along with the accounts it touched, all or nothing
+    ///
+    /// By default the record is stored first and then each account, storages that can fail
+    /// to store an account once another is stored should do it all at once.
+    fn commit(
+        &mut self,
+        record: Option<&TransactionRecord>,
+        accounts: Vec<Account>,
+    ) -> io::Result<()> {
+        if let Some(record) = record {
+            self.insert(record)?;
+        }
+        for account in accounts {
+            self.insert_account(account)?;
+        }
+        Ok(())
+    }
+}
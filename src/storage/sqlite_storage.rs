@@ -0,0 +1,315 @@
+use std::{borrow::Cow, collections::BTreeMap, io, path::Path};
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{
+    history::TransactionHistory,
+    model::{
+        account::Account,
+        id::{client_id::ClientId, transaction_id::TransactionId},
+        transaction_record::TransactionRecord,
+    },
+};
+
+use super::Storage;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS accounts (
+        client INTEGER PRIMARY KEY,
+        locked INTEGER NOT NULL,
+        account TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS balances (
+        client INTEGER NOT NULL,
+        currency TEXT,
+        available TEXT NOT NULL,
+        held TEXT NOT NULL,
+        total TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS balances_by_client ON balances (client);
+    CREATE TABLE IF NOT EXISTS transactions (
+        tx INTEGER PRIMARY KEY,
+        type TEXT NOT NULL,
+        client INTEGER NOT NULL,
+        amount TEXT,
+        currency TEXT,
+        state TEXT NOT NULL,
+        timestamp TEXT,
+        record TEXT NOT NULL
+    );
+";
+
+/// Keeps accounts and transactions in a SQLite database file
+///
+/// Every account and transaction is kept whole as json, alongside columns for querying:
+/// - `accounts` has `client` and `locked`
+/// - `balances` has the `available`, `held` and `total` balance of each `client` in each
+///   `currency`, which is null for the base currency
+/// - `transactions` has each `tx` with its `type`, `client`, `amount`, `currency`,
+///   dispute `state` and `timestamp`
+///
+/// Amounts are stored as text so that they stay exact, SQL arithmetic on them is approximate.
+#[derive(Debug)]
+pub struct SqliteStorage {
+    connection: Connection,
+    len: usize,
+}
+
+impl SqliteStorage {
+    /// Opens the database at the given path, creating it if it doesn't exist
+    ///
+    /// Accounts and transactions already in the database are kept.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<SqliteStorage> {
+        let connection = Connection::open(path).map_err(io::Error::other)?;
+        connection
+            .execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")
+            .map_err(io::Error::other)?;
+        connection.execute_batch(SCHEMA).map_err(io::Error::other)?;
+        let len = connection
+            .query_row("SELECT COUNT(*) FROM transactions", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .map_err(io::Error::other)?;
+        Ok(SqliteStorage {
+            connection,
+            len: len as usize,
+        })
+    }
+}
+
+impl TransactionHistory for SqliteStorage {
+    fn get(&self, tx: TransactionId) -> io::Result<Option<TransactionRecord>> {
+        let record = self
+            .connection
+            .prepare_cached("SELECT record FROM transactions WHERE tx = ?1")
+            .and_then(|mut statement| {
+                statement
+                    .query_row([tx.0], |row| row.get::<_, String>(0))
+                    .optional()
+            })
+            .map_err(io::Error::other)?;
+        match record {
+            Some(record) => Ok(Some(serde_json::from_str(&record)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn insert(&mut self, record: &TransactionRecord) -> io::Result<()> {
+        if insert_record(&self.connection, record)? {
+            self.len += 1;
+        }
+        Ok(())
+    }
+
+    fn records(&self) -> io::Result<Vec<TransactionRecord>> {
+        let rows = self
+            .connection
+            .prepare_cached("SELECT record FROM transactions ORDER BY tx")
+            .and_then(|mut statement| {
+                statement
+                    .query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(io::Error::other)?;
+        rows.iter()
+            .map(|row| Ok(serde_json::from_str(row)?))
+            .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn get_account(&self, client: ClientId) -> io::Result<Option<Cow<'_, Account>>> {
+        let account = self
+            .connection
+            .prepare_cached("SELECT account FROM accounts WHERE client = ?1")
+            .and_then(|mut statement| {
+                statement
+                    .query_row([client.0], |row| row.get::<_, String>(0))
+                    .optional()
+            })
+            .map_err(io::Error::other)?;
+        match account {
+            Some(account) => Ok(Some(Cow::Owned(serde_json::from_str(&account)?))),
+            None => Ok(None),
+        }
+    }
+
+    fn insert_account(&mut self, account: Account) -> io::Result<()> {
+        let transaction = self.connection.transaction().map_err(io::Error::other)?;
+        write_account(&transaction, &account)?;
+        transaction.commit().map_err(io::Error::other)
+    }
+
+    fn accounts(&self) -> io::Result<Cow<'_, BTreeMap<ClientId, Account>>> {
+        let rows = self
+            .connection
+            .prepare_cached("SELECT account FROM accounts ORDER BY client")
+            .and_then(|mut statement| {
+                statement
+                    .query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(io::Error::other)?;
+        rows.iter()
+            .map(|row| {
+                let account: Account = serde_json::from_str(row)?;
+                Ok((account.get_id(), account))
+            })
+            .collect::<io::Result<_>>()
+            .map(Cow::Owned)
+    }
+
+    fn commit(
+        &mut self,
+        record: Option<&TransactionRecord>,
+        accounts: Vec<Account>,
+    ) -> io::Result<()> {
+        let transaction = self.connection.transaction().map_err(io::Error::other)?;
+        let inserted = match record {
+            Some(record) => insert_record(&transaction, record)?,
+            None => false,
+        };
+        for account in &accounts {
+            write_account(&transaction, account)?;
+        }
+        transaction.commit().map_err(io::Error::other)?;
+        if inserted {
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Inserts or replaces a transaction's row, returning whether it is a new one
+fn insert_record(connection: &Connection, record: &TransactionRecord) -> io::Result<bool> {
+    let transaction = &record.transaction;
+    let row = params![
+        transaction.tx.0,
+        transaction.transaction_type.to_string(),
+        transaction.client.0,
+        transaction.amount.map(|amount| amount.to_string()),
+        transaction.currency.map(|currency| currency.to_string()),
+        record.state().to_string(),
+        transaction
+            .timestamp
+            .map(|timestamp| timestamp.to_rfc3339()),
+        serde_json::to_string(record)?,
+    ];
+    let inserted = connection
+        .prepare_cached(
+            "INSERT INTO transactions
+                (tx, type, client, amount, currency, state, timestamp, record)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            ON CONFLICT (tx) DO NOTHING",
+        )
+        .and_then(|mut statement| statement.execute(row))
+        .map_err(io::Error::other)?;
+    if inserted == 1 {
+        return Ok(true);
+    }
+    connection
+        .prepare_cached(
+            "UPDATE transactions SET
+                type = ?2, client = ?3, amount = ?4, currency = ?5,
+                state = ?6, timestamp = ?7, record = ?8
+            WHERE tx = ?1",
+        )
+        .and_then(|mut statement| statement.execute(row))
+        .map_err(io::Error::other)?;
+    Ok(false)
+}
+
+/// Inserts or replaces an account's row and its balances rows
+fn write_account(connection: &Connection, account: &Account) -> io::Result<()> {
+    let json = serde_json::to_string(account)?;
+    let client = account.get_id().0;
+    connection
+        .prepare_cached(
+            "INSERT INTO accounts (client, locked, account) VALUES (?1, ?2, ?3)
+            ON CONFLICT (client) DO UPDATE SET locked = ?2, account = ?3",
+        )
+        .and_then(|mut statement| statement.execute(params![client, account.is_locked(), json]))
+        .and_then(|_| connection.execute("DELETE FROM balances WHERE client = ?1", [client]))
+        .map_err(io::Error::other)?;
+    for currency in account.get_currencies() {
+        let balances = account.get_balances_in(currency);
+        connection
+            .prepare_cached(
+                "INSERT INTO balances (client, currency, available, held, total)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            )
+            .and_then(|mut statement| {
+                statement.execute(params![
+                    client,
+                    currency.map(|currency| currency.to_string()),
+                    balances.available.to_string(),
+                    balances.held.to_string(),
+                    balances.total.to_string(),
+                ])
+            })
+            .map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        model::{
+            dispute_state::DisputeState,
+            id::{client_id::ClientId, transaction_id::TransactionId},
+            transaction_type::TransactionType,
+        },
+        store::Store,
+        test_util::{temp_path, transaction},
+    };
+
+    use super::SqliteStorage;
+
+    #[test]
+    fn keeps_accounts_and_transactions_between_runs() {
+        let path = temp_path("sqlite.db");
+
+        let mut store = Store::new().with_storage(Box::new(SqliteStorage::open(&path).unwrap()));
+        for transaction in [
+            transaction(TransactionType::Deposit, 1, Some(10)),
+            transaction(TransactionType::Deposit, 2, Some(3)),
+            transaction(TransactionType::Dispute, 2, None),
+        ] {
+            store.apply_transaction(transaction).unwrap();
+        }
+        drop(store);
+
+        let storage = SqliteStorage::open(&path).unwrap();
+        let (available, held): (String, String) = storage
+            .connection
+            .query_row(
+                "SELECT available, held FROM balances WHERE client = 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((available.as_str(), held.as_str()), ("10", "3"));
+
+        let mut store = Store::new().with_storage(Box::new(storage));
+        assert_eq!(store.get_transactions().len(), 2);
+        store
+            .apply_transaction(transaction(TransactionType::Resolve, 2, None))
+            .unwrap();
+        let account = store.get_account(ClientId(1)).unwrap().unwrap();
+        assert_eq!(account.get_available(), 13.into());
+        let record = store.get_transactions().get(TransactionId(2)).unwrap();
+        assert_eq!(record.unwrap().state(), DisputeState::Resolved);
+
+        drop(store);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
@@ -1,106 +1,1374 @@
 //! The "Bank"
 
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::BTreeMap, io};
 
-use crate::model::{
-    account::Account,
-    error::transaction_error::TransactionError,
-    id::{client_id::ClientId, transaction_id::TransactionId},
-    transaction::Transaction,
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+
+use crate::{
+    history::TransactionHistory,
+    journal::{Journal, JournalEntry},
+    model::{
+        account::Account,
+        audit_entry::AuditEntry,
+        client_settings::ClientSettings,
+        dispute_state::DisputeState,
+        error::transaction_error::TransactionError,
+        id::{client_id::ClientId, transaction_id::TransactionId},
+        policy::{
+            account_policy::AccountPolicy, duplicate_policy::DuplicatePolicy,
+            redispute_policy::RedisputePolicy, withdrawal_dispute_policy::WithdrawalDisputePolicy,
+        },
+        rate_table::RateTable,
+        timeline::{Snapshot, Timeline},
+        transaction::Transaction,
+        transaction_record::TransactionRecord,
+        transaction_type::TransactionType,
+    },
+    snapshot::StoreSnapshot,
+    storage::{memory_storage::MemoryStorage, Storage},
 };
 
+/// What [apply_input](Store::apply_input) did with a row of input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputOutcome {
+    Applied,
+    /// Applied before the restored snapshot was saved, so left as it is
+    Skipped,
+}
+
 /// The core "bank" of the program
 ///
-/// Stores the map of accounts and transactions.
+/// Stores the accounts, ordered by client id, and the history of transactions.
 /// Handles transactions applied to accounts.
 ///
+/// Each stored transaction's [DisputeState] is tracked in its [TransactionRecord],
+/// so a resolved or charged back transaction isn't forgotten once it leaves the account's
+/// open disputes.
+///
+/// Every time an account is locked or unlocked an [AuditEntry] is kept,
+/// see [get_audit_log](Store::get_audit_log).
+///
+/// Everything is kept in memory by default, see [with_history](Store::with_history)
+/// for processing inputs too large to fit, and [with_storage](Store::with_storage) for
+/// keeping accounts and transactions elsewhere.
+///
 /// For more on how transactions are handled, see [Account].
 #[derive(Debug)]
 pub struct Store {
-    accounts: HashMap<ClientId, Account>,
-    transactions: HashMap<(ClientId, TransactionId), Transaction>,
+    storage: Box<dyn Storage>,
+    duplicate_policy: DuplicatePolicy,
+    account_policy: AccountPolicy,
+    redispute_policy: RedisputePolicy,
+    audit_log: Vec<AuditEntry>,
+    client_settings: BTreeMap<ClientId, ClientSettings>,
+    rates: RateTable,
+    latest: Option<DateTime<Utc>>,
+    /// Only kept once asked for with [with_timeline](Store::with_timeline)
+    timeline: Option<Timeline>,
+    dispute_window: Option<Duration>,
+    dispute_expiry: Option<Duration>,
+    /// The client and deadline of every open dispute that expires
+    dispute_deadlines: BTreeMap<TransactionId, (ClientId, DateTime<Utc>)>,
+    journal: Option<Journal>,
+    /// The sequence number of the next journaled transaction
+    journaled: u64,
+    /// The input rows are currently read from
+    source: Option<String>,
+    /// What became of each journaled row, by input and offset
+    resumed: BTreeMap<(String, u64), Result<InputOutcome, TransactionError>>,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Store::new()
+    }
 }
 
 impl Store {
     pub fn new() -> Store {
         Store {
-            accounts: HashMap::new(),
-            transactions: HashMap::new(),
+            storage: Box::new(MemoryStorage::default()),
+            duplicate_policy: DuplicatePolicy::default(),
+            account_policy: AccountPolicy::default(),
+            redispute_policy: RedisputePolicy::default(),
+            audit_log: Vec::new(),
+            client_settings: BTreeMap::new(),
+            rates: RateTable::default(),
+            latest: None,
+            timeline: None,
+            dispute_window: None,
+            dispute_expiry: None,
+            dispute_deadlines: BTreeMap::new(),
+            journal: None,
+            journaled: 0,
+            source: None,
+            resumed: BTreeMap::new(),
+        }
+    }
+
+    /// Replaces the transaction history, which should be empty, keeping accounts in memory
+    pub fn with_history(mut self, history: Box<dyn TransactionHistory>) -> Store {
+        self.storage = Box::new(MemoryStorage::new(history));
+        self
+    }
+
+    /// Replaces where accounts and transactions are kept
+    ///
+    /// Accounts and transactions already in the storage are carried on from.
+    pub fn with_storage(mut self, storage: Box<dyn Storage>) -> Store {
+        self.storage = storage;
+        self
+    }
+
+    /// Sets how deposits and withdrawals reusing an existing transaction id are handled
+    pub fn with_duplicate_policy(mut self, duplicate_policy: DuplicatePolicy) -> Store {
+        self.duplicate_policy = duplicate_policy;
+        self
+    }
+
+    /// Sets whether clients can dispute their withdrawals
+    pub fn with_withdrawal_dispute_policy(
+        mut self,
+        withdrawal_dispute_policy: WithdrawalDisputePolicy,
+    ) -> Store {
+        self.account_policy.withdrawal_disputes = withdrawal_dispute_policy;
+        self
+    }
+
+    /// Sets how far below zero a fee may take an account's available balance
+    ///
+    /// The limit can't be negative, that would refuse fees while the balance is still above zero.
+    pub fn with_fee_limit(mut self, fee_limit: Decimal) -> Store {
+        debug_assert!(
+            fee_limit >= Decimal::from(0),
+            "negative fee limit {}",
+            fee_limit
+        );
+        self.account_policy.fee_limit = fee_limit;
+        self
+    }
+
+    /// Sets up clients' accounts, such as their overdraft limits, as they are opened
+    ///
+    /// Should be set before any transactions are applied. A client listed more than once
+    /// gets their last settings.
+    pub fn with_client_settings(mut self, client_settings: Vec<ClientSettings>) -> Store {
+        for settings in client_settings {
+            self.client_settings.insert(settings.client, settings);
+        }
+        self
+    }
+
+    /// Sets the exchange rates conversions are made at
+    pub fn with_rates(mut self, rates: RateTable) -> Store {
+        self.rates = rates;
+        self
+    }
+
+    /// Sets whether resolved transactions can be disputed again
+    pub fn with_redispute_policy(mut self, redispute_policy: RedisputePolicy) -> Store {
+        self.redispute_policy = redispute_policy;
+        self
+    }
+
+    /// Sets how long after a transaction it can be disputed
+    ///
+    /// Only applies when both the dispute and the transaction have a timestamp.
+    pub fn with_dispute_window(mut self, dispute_window: Duration) -> Store {
+        self.dispute_window = Some(dispute_window);
+        self
+    }
+
+    /// Keeps the balances of every account a timestamped transaction touches, for
+    /// [get_balances_at](Store::get_balances_at)
+    ///
+    /// Every change is kept in memory, so it is best left off unless balances in the past are
+    /// looked up.
+    pub fn with_timeline(mut self) -> Store {
+        self.timeline = Some(Timeline::default());
+        self
+    }
+
+    /// Sets how long a timestamped dispute can stay open before it is resolved automatically
+    ///
+    /// Disputes are expired as time moves on with later timestamped transactions, each with a
+    /// `resolve` that is kept in the audit log.
+    pub fn with_dispute_expiry(mut self, dispute_expiry: Duration) -> Store {
+        self.dispute_expiry = Some(dispute_expiry);
+        self
+    }
+
+    /// Journals every transaction before it is applied, see [journal](crate::journal)
+    ///
+    /// The transactions already in the journal are replayed first, and rows of input they
+    /// were read from aren't applied again by [apply_input](Store::apply_input). Should be set
+    /// after any snapshot is restored, entries the snapshot already covers aren't replayed.
+    pub fn with_journal(mut self, mut journal: Journal) -> io::Result<Store> {
+        for entry in journal.entries()? {
+            let row = entry.source.zip(entry.offset);
+            if entry.sequence < self.journaled {
+                if let Some(row) = row {
+                    self.resumed.insert(row, Ok(InputOutcome::Skipped));
+                }
+                continue;
+            }
+            // Transactions the store refused the first time are refused again
+            let result = self
+                .apply_transaction(entry.transaction)
+                .map(|()| InputOutcome::Applied)
+                .map_err(|err| err.1);
+            if let Some(row) = row {
+                self.resumed.insert(row, result);
+            }
+            self.journaled = entry.sequence + 1;
+        }
+        self.journal = Some(journal);
+        Ok(self)
+    }
+
+    /// Empties the journal, once the store's state is saved or no longer needed
+    pub fn clear_journal(&mut self) -> io::Result<()> {
+        match &mut self.journal {
+            Some(journal) => journal.clear(),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets the name of the input that the following rows are read from
+    pub fn set_source(&mut self, source: String) {
+        self.source = Some(source);
+    }
+
+    /// Applies a transaction read from the current input at the given byte offset
+    ///
+    /// Rows the journal holds aren't applied again, as their transactions were replayed when
+    /// the journal was opened. They get the outcome of that replay instead, so a row refused
+    /// the first time is still reported, and rows the restored snapshot already covers are
+    /// [skipped](InputOutcome::Skipped).
+    #[allow(clippy::result_large_err)]
+    pub fn apply_input(
+        &mut self,
+        transaction: Transaction,
+        offset: u64,
+    ) -> Result<InputOutcome, (Transaction, TransactionError)> {
+        let resumed = match &self.source {
+            Some(source) => self.resumed.remove(&(source.clone(), offset)),
+            None => None,
+        };
+        match resumed {
+            Some(result) => result.map_err(|err| (transaction, err)),
+            None => self
+                .apply_journaled(transaction, Some(offset))
+                .map(|()| InputOutcome::Applied),
         }
     }
 
     /// A passthrough for [Account]'s [apply_transaction](Account::apply_transaction) method.
     ///
     /// Creates a new account if one doesn't exist.
-    /// Saves the transaction to the hashmap depending on the output of the account's
+    /// Saves the transaction to the history depending on the output of the account's
     /// [apply_transaction](Account::apply_transaction)
+    ///
+    /// Amounts are validated, and transactions that move money reusing a transaction id
+    /// are handled according to the store's [DuplicatePolicy], before any account is touched.
+    /// Likewise disputes of resolved or charged back transactions are checked against the
+    /// [RedisputePolicy], and disputes filed too late against the
+    /// [dispute window](Store::with_dispute_window).
+    ///
+    /// Conversions are given the rate for their currencies in effect at their timestamp,
+    /// or the latest rate without one, which is kept on the stored transaction.
+    ///
+    /// Timestamped transactions must come in time order, one earlier than the last applied
+    /// timestamp is rejected. Transactions without a timestamp are never out of order.
+    /// Once applied, the balances of the accounts a timestamped transaction touched are kept
+    /// for [get_balances_at](Store::get_balances_at), with a [timeline](Store::with_timeline).
+    ///
+    /// With a [journal](Store::with_journal), transactions that are valid and in time order
+    /// are journaled before any account is touched.
+    // The rejected transaction is handed back whole so that it can be reported
+    #[allow(clippy::result_large_err)]
     pub fn apply_transaction(
         &mut self,
         transaction: Transaction,
     ) -> Result<(), (Transaction, TransactionError)> {
-        let account = self
-            .accounts
-            .entry(transaction.client)
-            .or_insert_with(|| Account::new(transaction.client));
+        self.apply_journaled(transaction, None)
+    }
 
-        let transaction_result = account.apply_transaction(&self.transactions, &transaction);
+    /// Checks time order and journals the transaction, before applying it
+    #[allow(clippy::result_large_err)]
+    fn apply_journaled(
+        &mut self,
+        transaction: Transaction,
+        offset: Option<u64>,
+    ) -> Result<(), (Transaction, TransactionError)> {
+        let timestamp = match (transaction.timestamp, self.latest) {
+            (Some(timestamp), Some(latest)) if timestamp < latest => {
+                return Err((
+                    transaction,
+                    TransactionError::OutOfOrder { timestamp, latest },
+                ))
+            }
+            (timestamp, _) => timestamp,
+        };
+        if let Some(journal) = &mut self.journal {
+            if let Err(err) = transaction.validate() {
+                return Err((transaction, err));
+            }
+            let entry = JournalEntry {
+                sequence: self.journaled,
+                source: self.source.clone(),
+                offset,
+                transaction: transaction.clone(),
+            };
+            if let Err(err) = journal.append(&entry) {
+                return Err((transaction, err.into()));
+            }
+            self.journaled += 1;
+        }
+
+        self.apply(transaction)?;
+
+        if let Some(timestamp) = timestamp {
+            self.latest = Some(timestamp);
+            self.expire_disputes(timestamp);
+        }
+        Ok(())
+    }
+
+    /// Everything [apply_transaction](Store::apply_transaction) does apart from keeping time
+    ///
+    /// The balances of the accounts an applied timestamped transaction touched are recorded
+    /// in the timeline, if there is one.
+    #[allow(clippy::result_large_err)]
+    fn apply(
+        &mut self,
+        mut transaction: Transaction,
+    ) -> Result<(), (Transaction, TransactionError)> {
+        if let Err(err) = transaction.validate() {
+            return Err((transaction, err));
+        }
+
+        transaction.rate = None;
+        if transaction.transaction_type == TransactionType::Convert {
+            let (from, to) = (transaction.currency, transaction.to_currency);
+            match self.rates.rate(from, to, transaction.timestamp) {
+                Some(rate) => transaction.rate = Some(rate),
+                None => return Err((transaction, TransactionError::NoRate { from, to })),
+            }
+        }
+
+        if transaction.transaction_type.has_amount() {
+            let original = match self.storage.get(transaction.tx) {
+                Ok(original) => original,
+                Err(err) => return Err((transaction, err.into())),
+            };
+            if let Some(original) = original {
+                return match self.duplicate_policy {
+                    DuplicatePolicy::Ignore => Ok(()),
+                    DuplicatePolicy::Idempotent
+                        if transaction.is_replay_of(&original.transaction) =>
+                    {
+                        Ok(())
+                    }
+                    _ => {
+                        let tx = transaction.tx;
+                        Err((transaction, TransactionError::DuplicateTransaction { tx }))
+                    }
+                };
+            }
+        }
+
+        if transaction.transaction_type == TransactionType::Dispute {
+            if let Err(err) = self.check_dispute(&transaction) {
+                return Err((transaction, err));
+            }
+        }
+
+        // Both sides of a transfer are checked before either is applied, the sending account
+        // checks for locks and overdrafts like a withdrawal
+        let destination = match transaction.destination {
+            Some(destination) => match self.load_account(destination) {
+                Ok(account) if account.is_locked() => {
+                    return Err((
+                        transaction,
+                        TransactionError::DestinationLocked { destination },
+                    ))
+                }
+                Ok(account) => Some(account),
+                Err(err) => return Err((transaction, err.into())),
+            },
+            None => None,
+        };
+
+        let mut account = match self.load_account(transaction.client) {
+            Ok(account) => account,
+            Err(err) => return Err((transaction, err.into())),
+        };
+
+        let was_locked = account.is_locked();
+        let transaction_result =
+            account.apply_transaction(self.storage.as_ref(), &transaction, &self.account_policy);
+
+        let mut touched = vec![account];
+        if let (Ok(_), Some(mut destination)) = (&transaction_result, destination) {
+            destination.receive_transfer(&transaction);
+            touched.push(destination);
+        }
+        // The record and the accounts are stored together, so that an error leaves both as
+        // they were. A rejected transaction still opens the client's account.
+        let record = match &transaction_result {
+            Ok(true) => Ok(Some(TransactionRecord::new(transaction.clone()))),
+            Ok(false) => self.advanced_record(&transaction),
+            Err(_) => Ok(None),
+        };
+        let is_locked = touched[0].is_locked();
+        let timeline_accounts = match (&transaction_result, transaction.timestamp, &self.timeline) {
+            (Ok(_), Some(timestamp), Some(_)) => Some((timestamp, touched.clone())),
+            _ => None,
+        };
+        let committed = record.and_then(|record| self.storage.commit(record.as_ref(), touched));
+        if let Err(err) = committed {
+            return Err((transaction, err.into()));
+        }
+
+        if is_locked != was_locked {
+            self.audit_log.push(AuditEntry {
+                client: transaction.client,
+                tx: transaction.tx,
+                transaction_type: transaction.transaction_type,
+                locked: is_locked,
+                reason: transaction.reason.clone(),
+            });
+        }
+        if let (Some((timestamp, accounts)), Some(timeline)) =
+            (timeline_accounts, &mut self.timeline)
+        {
+            for account in &accounts {
+                timeline.record(timestamp, account);
+            }
+        }
 
         match transaction_result {
-            Ok(true) => {
-                self.transactions
-                    .insert((transaction.client, transaction.tx), transaction);
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                self.track_dispute_deadline(&transaction);
                 Ok(())
             }
             Err(err) => Err((transaction, err)),
+        }
+    }
+
+    /// Refuses disputes of transactions that have already been through the dispute lifecycle,
+    /// or that are filed after the dispute window closed
+    ///
+    /// Transactions of other clients are left for the account to reject.
+    fn check_dispute(&self, transaction: &Transaction) -> Result<(), TransactionError> {
+        let record = match self.storage.get(transaction.tx)? {
+            Some(record) if record.transaction.client == transaction.client => record,
+            _ => return Ok(()),
+        };
+        let tx = transaction.tx;
+        let deadline = match (self.dispute_window, record.transaction.timestamp) {
+            (Some(window), Some(timestamp)) => timestamp.checked_add_signed(window),
+            _ => None,
+        };
+        if let (Some(deadline), Some(filed)) = (deadline, transaction.timestamp) {
+            if filed > deadline {
+                return Err(TransactionError::DisputeWindowClosed { tx, deadline });
+            }
+        }
+        match record.state() {
+            DisputeState::ChargedBack => Err(TransactionError::AlreadyChargedBack { tx }),
+            DisputeState::Resolved if self.redispute_policy == RedisputePolicy::Reject => {
+                Err(TransactionError::AlreadyResolved { tx })
+            }
             _ => Ok(()),
         }
     }
 
-    pub fn get_accounts(&self) -> &HashMap<ClientId, Account> {
-        &self.accounts
+    /// Keeps the deadline of an applied timestamped dispute, and forgets it once the dispute
+    /// is resolved or charged back
+    fn track_dispute_deadline(&mut self, transaction: &Transaction) {
+        match transaction.transaction_type {
+            TransactionType::Dispute => {
+                let deadline = match (self.dispute_expiry, transaction.timestamp) {
+                    (Some(expiry), Some(timestamp)) => timestamp.checked_add_signed(expiry),
+                    _ => None,
+                };
+                if let Some(deadline) = deadline {
+                    self.dispute_deadlines
+                        .insert(transaction.tx, (transaction.client, deadline));
+                }
+            }
+            TransactionType::Resolve | TransactionType::Chargeback => {
+                self.dispute_deadlines.remove(&transaction.tx);
+            }
+            _ => {}
+        }
+    }
+
+    /// Resolves every dispute whose deadline has passed by the given time, earliest first
+    ///
+    /// Each is resolved with a `resolve` timestamped at its deadline, or at the latest applied
+    /// timestamp if that is later, which is kept in the audit log. Disputes on locked accounts
+    /// stay open until the account is unlocked.
+    fn expire_disputes(&mut self, now: DateTime<Utc>) {
+        let mut expired = self
+            .dispute_deadlines
+            .iter()
+            .filter(|(_, (_, deadline))| *deadline < now)
+            .map(|(tx, (client, deadline))| (*deadline, *tx, *client))
+            .collect::<Vec<_>>();
+        expired.sort();
+        for (deadline, tx, client) in expired {
+            let resolve = Transaction {
+                transaction_type: TransactionType::Resolve,
+                client,
+                tx,
+                amount: None,
+                destination: None,
+                currency: None,
+                to_currency: None,
+                rate: None,
+                reason: Some(DISPUTE_EXPIRED.to_string()),
+                timestamp: Some(self.latest.map_or(deadline, |latest| latest.max(deadline))),
+            };
+            match self.apply(resolve) {
+                Ok(()) => {}
+                Err((_, TransactionError::AccountLocked)) => continue,
+                Err(_) => {
+                    self.dispute_deadlines.remove(&tx);
+                    continue;
+                }
+            }
+            // Only the disputes of unlocked accounts are resolved
+            self.audit_log.push(AuditEntry {
+                client,
+                tx,
+                transaction_type: TransactionType::Resolve,
+                locked: false,
+                reason: Some(DISPUTE_EXPIRED.to_string()),
+            });
+        }
+    }
+
+    /// The record of the transaction an applied dispute, resolve or chargeback refers to,
+    /// moved to the state it entered
+    fn advanced_record(&self, transaction: &Transaction) -> io::Result<Option<TransactionRecord>> {
+        let state = match DisputeState::entered_by(transaction.transaction_type) {
+            Some(state) => state,
+            None => return Ok(None),
+        };
+        let mut record = self.storage.get(transaction.tx)?;
+        if let Some(record) = &mut record {
+            record.transition(state);
+        }
+        Ok(record)
+    }
+
+    /// Looks up a client's account, opening a new one if they don't have one yet
+    ///
+    /// The client's current settings apply whether the account is new or not.
+    /// The account is a copy, so that the stored one is left as it was until the
+    /// transaction is committed.
+    fn load_account(&self, client: ClientId) -> io::Result<Account> {
+        let mut account = match self.storage.get_account(client)? {
+            Some(account) => account.into_owned(),
+            None => Account::new(client),
+        };
+        if let Some(settings) = self.client_settings.get(&client) {
+            account.set_overdraft_limit(settings.overdraft_limit);
+        }
+        Ok(account)
+    }
+
+    pub fn get_account(&self, client: ClientId) -> io::Result<Option<Cow<'_, Account>>> {
+        self.storage.get_account(client)
+    }
+
+    pub fn get_accounts(&self) -> io::Result<Cow<'_, BTreeMap<ClientId, Account>>> {
+        self.storage.accounts()
+    }
+
+    pub fn get_transactions(&self) -> &dyn TransactionHistory {
+        self.storage.as_ref()
+    }
+
+    /// Everything needed to carry on from the store's current state in a later run,
+    /// see [snapshot](crate::snapshot)
+    pub fn snapshot(&self) -> io::Result<StoreSnapshot> {
+        let mut transactions = self.storage.records()?;
+        transactions.sort_by_key(|record| record.transaction.tx);
+        Ok(StoreSnapshot {
+            accounts: self.storage.accounts()?.values().cloned().collect(),
+            transactions,
+            latest: self.latest,
+            dispute_deadlines: self
+                .dispute_deadlines
+                .iter()
+                .map(|(tx, (client, deadline))| (*tx, *client, *deadline))
+                .collect(),
+            journaled: self.journaled,
+        })
+    }
+
+    /// Carries on from a snapshot taken by an earlier run
+    ///
+    /// Should be called on a new store, after its history and settings are set up.
+    /// The current client settings replace those the accounts were saved with.
+    pub fn restore(mut self, snapshot: StoreSnapshot) -> io::Result<Store> {
+        for record in &snapshot.transactions {
+            self.storage.insert(record)?;
+        }
+        for mut account in snapshot.accounts {
+            if let Some(settings) = self.client_settings.get(&account.get_id()) {
+                account.set_overdraft_limit(settings.overdraft_limit);
+            }
+            self.storage.insert_account(account)?;
+        }
+        self.latest = snapshot.latest;
+        self.journaled = snapshot.journaled;
+        self.dispute_deadlines = snapshot
+            .dispute_deadlines
+            .into_iter()
+            .map(|(tx, client, deadline)| (tx, (client, deadline)))
+            .collect();
+        Ok(self)
+    }
+
+    /// A new store set up the same way, holding only the accounts and transactions of the
+    /// clients `owns` picks, for applying those clients' transactions on their own
+    ///
+    /// The shard keeps everything in memory, and its changes are brought back with
+    /// [absorb](Store::absorb). Used by the [pipeline](crate::pipeline).
+    pub fn shard<F: Fn(ClientId) -> bool>(&self, owns: F) -> io::Result<Store> {
+        let mut shard = Store {
+            duplicate_policy: self.duplicate_policy,
+            account_policy: self.account_policy,
+            redispute_policy: self.redispute_policy,
+            client_settings: self.client_settings.clone(),
+            rates: self.rates.clone(),
+            latest: self.latest,
+            dispute_window: self.dispute_window,
+            dispute_expiry: self.dispute_expiry,
+            timeline: self.timeline.as_ref().map(|_| Timeline::default()),
+            ..Store::new()
+        };
+        for record in self.storage.records()? {
+            if owns(record.transaction.client) {
+                shard.storage.insert(&record)?;
+            }
+        }
+        for account in self.storage.accounts()?.values() {
+            if owns(account.get_id()) {
+                shard.storage.insert_account(account.clone())?;
+            }
+        }
+        shard.dispute_deadlines = self
+            .dispute_deadlines
+            .iter()
+            .filter(|(_, (client, _))| owns(*client))
+            .map(|(tx, deadline)| (*tx, *deadline))
+            .collect();
+        Ok(shard)
+    }
+
+    /// Brings back the accounts and transactions of the clients `owns` picks from a
+    /// [shard](Store::shard)
+    ///
+    /// Audit entries aren't brought back, as only the caller knows the order they were made in.
+    pub fn absorb<F: Fn(ClientId) -> bool>(&mut self, shard: Store, owns: F) -> io::Result<()> {
+        for account in shard.storage.accounts()?.values() {
+            if owns(account.get_id()) {
+                self.storage.insert_account(account.clone())?;
+            }
+        }
+        for record in shard.storage.into_records()? {
+            if owns(record.transaction.client) {
+                self.storage.insert(&record)?;
+            }
+        }
+        if let (Some(timeline), Some(shard_timeline)) = (&mut self.timeline, shard.timeline) {
+            timeline.absorb(shard_timeline, &owns);
+        }
+        self.latest = self.latest.max(shard.latest);
+        self.dispute_deadlines
+            .retain(|_, (client, _)| !owns(*client));
+        self.dispute_deadlines.extend(shard.dispute_deadlines);
+        Ok(())
+    }
+
+    /// Keeps a copy of another store's transaction, so that reusing its id is a duplicate
+    ///
+    /// The copy belongs to another client, so it can't be disputed from this store.
+    pub fn share_transaction(&mut self, record: &TransactionRecord) -> io::Result<()> {
+        self.storage.insert(record)
+    }
+
+    /// Keeps a copy of another store's account, such as the destination of a transfer
+    ///
+    /// When `at` is given, the account's balances are recorded in any timeline at that time.
+    pub fn share_account(
+        &mut self,
+        account: &Account,
+        at: Option<DateTime<Utc>>,
+    ) -> io::Result<()> {
+        if let (Some(at), Some(timeline)) = (at, &mut self.timeline) {
+            timeline.record(at, account);
+        }
+        self.storage.insert_account(account.clone())
+    }
+
+    /// Adds entries to the audit log, such as those made by [shards](Store::shard)
+    pub fn extend_audit_log<I: IntoIterator<Item = AuditEntry>>(&mut self, entries: I) {
+        self.audit_log.extend(entries);
     }
 
-    pub fn get_transactions(&self) -> &HashMap<(ClientId, TransactionId), Transaction> {
-        &self.transactions
+    /// How long a dispute is left open, see [with_dispute_expiry](Store::with_dispute_expiry)
+    pub fn get_dispute_expiry(&self) -> Option<Duration> {
+        self.dispute_expiry
+    }
+
+    /// The sequence number the next journaled transaction is given
+    pub fn get_journaled(&self) -> u64 {
+        self.journaled
+    }
+
+    /// Counts the transactions journaled on the store's behalf up to `journaled`, such as by
+    /// a [ConcurrentStore](crate::concurrent_store::ConcurrentStore) split from it
+    pub fn advance_journaled(&mut self, journaled: u64) {
+        self.journaled = self.journaled.max(journaled);
+    }
+
+    /// The latest timestamp of an applied transaction
+    pub fn get_latest(&self) -> Option<DateTime<Utc>> {
+        self.latest
+    }
+
+    /// Rejects transactions timestamped before `latest` from now on, as if a transaction
+    /// at that time had been applied
+    ///
+    /// Disputes aren't expired.
+    pub fn advance_to(&mut self, latest: DateTime<Utc>) {
+        self.latest = self.latest.max(Some(latest));
+    }
+
+    /// A client's balances in each currency as of the given time
+    ///
+    /// Only timestamped transactions are kept track of, [None] if the client had none by then
+    /// or the store keeps no [timeline](Store::with_timeline).
+    pub fn get_balances_at(&self, client: ClientId, at: DateTime<Utc>) -> Option<&Snapshot> {
+        self.timeline.as_ref()?.balances_at(client, at)
+    }
+
+    pub fn get_client_settings(&self) -> &BTreeMap<ClientId, ClientSettings> {
+        &self.client_settings
+    }
+
+    /// Every change to whether an account is locked, and every dispute resolved because it
+    /// expired, in the order they happened
+    pub fn get_audit_log(&self) -> &[AuditEntry] {
+        &self.audit_log
     }
 }
 
+/// The reason given for resolving a dispute that was left open past its deadline
+pub const DISPUTE_EXPIRED: &str = "dispute expired";
+
 #[cfg(test)]
 mod tests {
+    use std::io;
+
+    use chrono::Duration;
+
+    use crate::history::{memory_history::MemoryHistory, TransactionHistory};
     use crate::model::{
+        dispute_state::DisputeState,
+        error::transaction_error::TransactionError,
+        exchange_rate::ExchangeRate,
         id::{client_id::ClientId, transaction_id::TransactionId},
+        policy::{duplicate_policy::DuplicatePolicy, redispute_policy::RedisputePolicy},
+        rate_table::RateTable,
         transaction::Transaction,
+        transaction_record::TransactionRecord,
         transaction_type::TransactionType,
     };
 
-    use super::Store;
+    use crate::{
+        journal::Journal,
+        test_util::{temp_path, transaction},
+    };
+
+    use super::{InputOutcome, Store, DISPUTE_EXPIRED};
+
+    fn deposit(client: u16, tx: u32, amount: &str) -> Transaction {
+        Transaction {
+            client: ClientId(client),
+            amount: Some(amount.parse().unwrap()),
+            ..transaction(TransactionType::Deposit, tx, None)
+        }
+    }
 
     #[test]
     fn creates_accounts_and_transactions() {
         let mut store = Store::new();
         let mut deposit = Transaction {
-            transaction_type: TransactionType::Deposit,
             client: ClientId(0),
-            tx: TransactionId(1),
-            amount: Some(10.into()),
+            ..transaction(TransactionType::Deposit, 1, Some(10))
         };
 
-        assert_eq!(store.get_accounts().len(), 0);
+        assert_eq!(store.get_accounts().unwrap().len(), 0);
         assert_eq!(store.get_transactions().len(), 0);
 
         deposit.client = ClientId(1);
         assert!(store.apply_transaction(deposit.clone()).is_ok());
         deposit.client = ClientId(2);
+        deposit.tx = TransactionId(2);
         assert!(store.apply_transaction(deposit.clone()).is_ok());
         deposit.client = ClientId(3);
+        deposit.tx = TransactionId(3);
         assert!(store.apply_transaction(deposit.clone()).is_ok());
 
-        assert_eq!(store.get_accounts().len(), 3);
+        assert_eq!(store.get_accounts().unwrap().len(), 3);
         assert_eq!(store.get_transactions().len(), 3);
 
         deposit.transaction_type = TransactionType::Dispute;
+        deposit.amount = None;
         assert!(store.apply_transaction(deposit.clone()).is_ok());
-        assert_eq!(store.get_accounts().len(), 3);
+        assert_eq!(store.get_accounts().unwrap().len(), 3);
         assert_eq!(store.get_transactions().len(), 3);
     }
+
+    #[test]
+    fn rejects_duplicate_transactions() {
+        let mut store = Store::new();
+        assert!(store.apply_transaction(deposit(1, 1, "10")).is_ok());
+
+        let result = store.apply_transaction(deposit(2, 1, "5"));
+        assert!(matches!(
+            result,
+            Err((
+                _,
+                TransactionError::DuplicateTransaction {
+                    tx: TransactionId(1)
+                }
+            ))
+        ));
+        assert_eq!(store.get_accounts().unwrap().len(), 1);
+        let original = store.get_transactions().get(TransactionId(1)).unwrap();
+        assert_eq!(original.unwrap().transaction.client, ClientId(1));
+    }
+
+    #[test]
+    fn ignores_duplicate_transactions() {
+        let mut store = Store::new().with_duplicate_policy(DuplicatePolicy::Ignore);
+        assert!(store.apply_transaction(deposit(1, 1, "10")).is_ok());
+        assert!(store.apply_transaction(deposit(1, 1, "5")).is_ok());
+
+        let account = store.get_account(ClientId(1)).unwrap().unwrap();
+        assert_eq!(account.get_available(), 10.into());
+    }
+
+    #[test]
+    fn replays_identical_transactions() {
+        let mut store = Store::new().with_duplicate_policy(DuplicatePolicy::Idempotent);
+        assert!(store.apply_transaction(deposit(1, 1, "10.0")).is_ok());
+        assert!(store.apply_transaction(deposit(1, 1, "10.0")).is_ok());
+        assert!(store.apply_transaction(deposit(1, 1, "10.00")).is_err());
+        assert!(store.apply_transaction(deposit(2, 1, "10.0")).is_err());
+
+        let account = store.get_account(ClientId(1)).unwrap().unwrap();
+        assert_eq!(account.get_available(), 10.into());
+        assert_eq!(store.get_accounts().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn validates_amounts_before_creating_accounts() {
+        let mut store = Store::new();
+        let result = store.apply_transaction(deposit(1, 1, "-10"));
+        assert!(matches!(
+            result,
+            Err((_, TransactionError::NonPositiveAmount { .. }))
+        ));
+        assert_eq!(store.get_accounts().unwrap().len(), 0);
+        assert_eq!(store.get_transactions().len(), 0);
+    }
+
+    #[test]
+    fn refuses_amounts_that_could_overflow_a_balance() {
+        let mut store = Store::new();
+        let huge = "50000000000000000000000000000";
+        assert!(store.apply_transaction(deposit(1, 1, huge)).is_err());
+        assert!(store.apply_transaction(deposit(1, 2, huge)).is_err());
+        assert!(store
+            .apply_transaction(deposit(1, 3, "1000000000000"))
+            .is_ok());
+        assert!(store
+            .apply_transaction(deposit(1, 4, "1000000000000"))
+            .is_ok());
+        assert_eq!(
+            store.get_accounts().unwrap()[&ClientId(1)].get_available(),
+            2_000_000_000_000i64.into()
+        );
+    }
+
+    fn refer(transaction_type: TransactionType, client: u16, tx: u32) -> Transaction {
+        Transaction {
+            client: ClientId(client),
+            ..transaction(transaction_type, tx, None)
+        }
+    }
+
+    #[test]
+    fn tracks_dispute_lifecycle() {
+        let mut store = Store::new();
+        assert!(store.apply_transaction(deposit(1, 1, "10")).is_ok());
+        for transaction_type in [
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Dispute,
+            TransactionType::Chargeback,
+        ] {
+            assert!(store
+                .apply_transaction(refer(transaction_type, 1, 1))
+                .is_ok());
+        }
+
+        let record = store
+            .get_transactions()
+            .get(TransactionId(1))
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.state(), DisputeState::ChargedBack);
+        assert_eq!(
+            record.get_states(),
+            [
+                DisputeState::Processed,
+                DisputeState::Disputed,
+                DisputeState::Resolved,
+                DisputeState::Disputed,
+                DisputeState::ChargedBack,
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_redisputes() {
+        let mut store = Store::new().with_redispute_policy(RedisputePolicy::Reject);
+        assert!(store.apply_transaction(deposit(1, 1, "10")).is_ok());
+        assert!(store
+            .apply_transaction(refer(TransactionType::Dispute, 1, 1))
+            .is_ok());
+        assert!(store
+            .apply_transaction(refer(TransactionType::Resolve, 1, 1))
+            .is_ok());
+
+        let result = store.apply_transaction(refer(TransactionType::Dispute, 1, 1));
+        assert!(matches!(
+            result,
+            Err((
+                _,
+                TransactionError::AlreadyResolved {
+                    tx: TransactionId(1)
+                }
+            ))
+        ));
+        let record = store
+            .get_transactions()
+            .get(TransactionId(1))
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.state(), DisputeState::Resolved);
+    }
+
+    #[test]
+    fn freezes_and_unlocks_accounts() {
+        let mut store = Store::new();
+        let admin = |transaction_type, tx, reason: Option<&str>| Transaction {
+            reason: reason.map(String::from),
+            ..refer(transaction_type, 1, tx)
+        };
+        assert!(store.apply_transaction(deposit(1, 1, "10")).is_ok());
+        assert!(matches!(
+            store.apply_transaction(admin(TransactionType::Freeze, 2, None)),
+            Err((_, TransactionError::MissingReason))
+        ));
+        assert!(store
+            .apply_transaction(admin(TransactionType::Freeze, 2, Some("kyc")))
+            .is_ok());
+        assert!(matches!(
+            store.apply_transaction(deposit(1, 3, "5")),
+            Err((_, TransactionError::AccountLocked))
+        ));
+        assert!(store
+            .apply_transaction(admin(TransactionType::Unlock, 4, Some("kyc cleared")))
+            .is_ok());
+        assert!(matches!(
+            store.apply_transaction(admin(TransactionType::Unlock, 5, Some("again"))),
+            Err((_, TransactionError::AccountNotLocked))
+        ));
+        assert!(store.apply_transaction(deposit(1, 3, "5")).is_ok());
+
+        let account = store.get_account(ClientId(1)).unwrap().unwrap();
+        assert!(!account.is_locked());
+        assert_eq!(account.get_available(), 15.into());
+
+        let audit_log = store.get_audit_log();
+        assert_eq!(audit_log.len(), 2);
+        assert_eq!(audit_log[0].tx, TransactionId(2));
+        assert!(audit_log[0].locked);
+        assert_eq!(audit_log[1].reason.as_deref(), Some("kyc cleared"));
+        assert!(!audit_log[1].locked);
+    }
+
+    fn transfer(client: u16, tx: u32, amount: &str, destination: u16) -> Transaction {
+        Transaction {
+            transaction_type: TransactionType::Transfer,
+            destination: Some(ClientId(destination)),
+            currency: None,
+            to_currency: None,
+            rate: None,
+            amount: Some(amount.parse().unwrap()),
+            ..refer(TransactionType::Transfer, client, tx)
+        }
+    }
+
+    #[test]
+    fn transfers_between_accounts() {
+        let mut store = Store::new();
+        assert!(store.apply_transaction(deposit(1, 1, "10")).is_ok());
+        assert!(store.apply_transaction(transfer(1, 2, "4", 2)).is_ok());
+        assert!(matches!(
+            store.apply_transaction(transfer(1, 3, "7", 2)),
+            Err((_, TransactionError::Overdraft { .. }))
+        ));
+        assert!(matches!(
+            store.apply_transaction(refer(TransactionType::Dispute, 1, 2)),
+            Err((_, TransactionError::NotDisputable { .. }))
+        ));
+
+        let accounts = store.get_accounts().unwrap();
+        assert_eq!(accounts[&ClientId(1)].get_available(), 6.into());
+        assert_eq!(accounts[&ClientId(2)].get_available(), 4.into());
+        assert_eq!(store.get_transactions().len(), 2);
+    }
+
+    /// A history that stops storing transactions after a number of them
+    #[derive(Debug)]
+    struct FailingHistory {
+        history: MemoryHistory,
+        inserts: usize,
+    }
+
+    impl TransactionHistory for FailingHistory {
+        fn get(&self, tx: TransactionId) -> io::Result<Option<TransactionRecord>> {
+            self.history.get(tx)
+        }
+
+        fn insert(&mut self, record: &TransactionRecord) -> io::Result<()> {
+            if self.inserts == 0 {
+                return Err(io::Error::other("disk full"));
+            }
+            self.inserts -= 1;
+            self.history.insert(record)
+        }
+
+        fn records(&self) -> io::Result<Vec<TransactionRecord>> {
+            self.history.records()
+        }
+
+        fn len(&self) -> usize {
+            self.history.len()
+        }
+    }
+
+    #[test]
+    fn leaves_accounts_as_they_were_when_a_record_cant_be_stored() {
+        let history = FailingHistory {
+            history: MemoryHistory::new(),
+            inserts: 1,
+        };
+        let mut store = Store::new().with_history(Box::new(history));
+        assert!(store.apply_transaction(deposit(1, 1, "10")).is_ok());
+        assert!(matches!(
+            store.apply_transaction(transfer(1, 2, "4", 2)),
+            Err((_, TransactionError::Storage(_)))
+        ));
+
+        let accounts = store.get_accounts().unwrap();
+        assert_eq!(accounts[&ClientId(1)].get_available(), 10.into());
+        assert!(!accounts.contains_key(&ClientId(2)));
+    }
+
+    #[test]
+    fn transfers_to_locked_accounts_change_neither_side() {
+        let mut store = Store::new();
+        assert!(store.apply_transaction(deposit(1, 1, "10")).is_ok());
+        let freeze = Transaction {
+            reason: Some("fraud review".to_string()),
+            ..refer(TransactionType::Freeze, 2, 2)
+        };
+        assert!(store.apply_transaction(freeze).is_ok());
+
+        let result = store.apply_transaction(transfer(1, 3, "4", 2));
+        assert!(matches!(
+            result,
+            Err((
+                _,
+                TransactionError::DestinationLocked {
+                    destination: ClientId(2)
+                }
+            ))
+        ));
+        let accounts = store.get_accounts().unwrap();
+        assert_eq!(accounts[&ClientId(1)].get_available(), 10.into());
+        assert_eq!(accounts[&ClientId(2)].get_available(), 0.into());
+        assert!(store
+            .get_transactions()
+            .get(TransactionId(3))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn converts_at_the_recorded_rate() {
+        let eur = Some("EUR".parse().unwrap());
+        let rate = |rate: &str, effective: &str| ExchangeRate {
+            from: None,
+            to: eur,
+            rate: rate.parse().unwrap(),
+            effective: effective.parse().unwrap(),
+        };
+        let mut store = Store::new().with_rates(RateTable::new(vec![
+            rate("0.5", "2024-05-01T00:00:00Z"),
+            rate("0.9", "2024-05-02T00:00:00Z"),
+        ]));
+        let convert = |tx, to_currency| Transaction {
+            transaction_type: TransactionType::Convert,
+            to_currency,
+            rate: Some(100.into()),
+            ..deposit(1, tx, "4")
+        };
+
+        assert!(store.apply_transaction(deposit(1, 1, "10")).is_ok());
+        assert!(matches!(
+            store.apply_transaction(convert(2, Some("GBP".parse().unwrap()))),
+            Err((_, TransactionError::NoRate { .. }))
+        ));
+        assert!(store.apply_transaction(convert(3, eur)).is_ok());
+        let account = store.get_account(ClientId(1)).unwrap().unwrap();
+        assert_eq!(account.get_available(), 6.into());
+        assert_eq!(
+            account.get_available_in(eur.as_ref()),
+            "3.6".parse().unwrap()
+        );
+        let record = store.get_transactions().get(TransactionId(3)).unwrap();
+        assert_eq!(
+            record.unwrap().transaction.rate,
+            Some("0.9".parse().unwrap())
+        );
+
+        store = store.with_rates(RateTable::default());
+        assert!(store
+            .apply_transaction(refer(TransactionType::Dispute, 1, 3))
+            .is_ok());
+        let account = store.get_account(ClientId(1)).unwrap().unwrap();
+        assert_eq!(account.get_available_in(eur.as_ref()), 0.into());
+        assert_eq!(account.get_held_in(eur.as_ref()), "3.6".parse().unwrap());
+        assert!(store
+            .apply_transaction(refer(TransactionType::Chargeback, 1, 3))
+            .is_ok());
+        let account = store.get_account(ClientId(1)).unwrap().unwrap();
+        assert_eq!(account.get_available(), 10.into());
+        assert_eq!(account.get_total_in(eur.as_ref()), 0.into());
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn keeps_time_order_and_balances_over_time() {
+        let mut store = Store::new().with_timeline();
+        let at = |deposit: Transaction, timestamp: &str| Transaction {
+            timestamp: Some(timestamp.parse().unwrap()),
+            ..deposit
+        };
+        let time = |time: &str| time.parse().unwrap();
+
+        assert!(store
+            .apply_transaction(at(deposit(1, 1, "10"), "2024-05-01T09:00:00Z"))
+            .is_ok());
+        assert!(store.apply_transaction(deposit(1, 2, "1")).is_ok());
+        assert!(store
+            .apply_transaction(at(deposit(1, 3, "5"), "2024-05-02T09:00:00Z"))
+            .is_ok());
+        assert!(matches!(
+            store.apply_transaction(at(deposit(1, 4, "7"), "2024-05-01T12:00:00Z")),
+            Err((_, TransactionError::OutOfOrder { .. }))
+        ));
+
+        let available_at = |at| {
+            store
+                .get_balances_at(ClientId(1), time(at))
+                .map(|snapshot| snapshot[&None].available)
+        };
+        assert_eq!(available_at("2024-04-30T00:00:00Z"), None);
+        assert_eq!(available_at("2024-05-01T23:00:00Z"), Some(10.into()));
+        assert_eq!(available_at("2024-05-02T09:00:00Z"), Some(16.into()));
+        assert!(Store::new()
+            .get_balances_at(ClientId(1), time("2024-05-02T09:00:00Z"))
+            .is_none());
+        assert_eq!(
+            store.get_accounts().unwrap()[&ClientId(1)].get_available(),
+            16.into()
+        );
+    }
+
+    #[test]
+    fn closes_dispute_windows_and_expires_disputes() {
+        let mut store = Store::new()
+            .with_dispute_window(Duration::days(30))
+            .with_dispute_expiry(Duration::days(14))
+            .with_timeline();
+        let at = |transaction: Transaction, timestamp: &str| Transaction {
+            timestamp: Some(timestamp.parse().unwrap()),
+            ..transaction
+        };
+
+        for transaction in [
+            at(deposit(1, 2, "5"), "2024-04-01T00:00:00Z"),
+            at(deposit(1, 1, "10"), "2024-05-01T00:00:00Z"),
+            at(
+                refer(TransactionType::Dispute, 1, 1),
+                "2024-05-02T00:00:00Z",
+            ),
+        ] {
+            assert!(store.apply_transaction(transaction).is_ok());
+        }
+        assert!(matches!(
+            store.apply_transaction(at(
+                refer(TransactionType::Dispute, 1, 2),
+                "2024-05-05T00:00:00Z"
+            )),
+            Err((
+                _,
+                TransactionError::DisputeWindowClosed {
+                    tx: TransactionId(2),
+                    ..
+                }
+            ))
+        ));
+        assert_eq!(
+            store.get_accounts().unwrap()[&ClientId(1)].get_held(),
+            10.into()
+        );
+        assert!(store.get_audit_log().is_empty());
+
+        // A rejected row doesn't move time on, so it doesn't expire the dispute
+        let withdraw = Transaction {
+            transaction_type: TransactionType::Withdraw,
+            ..deposit(2, 3, "1")
+        };
+        assert!(matches!(
+            store.apply_transaction(at(withdraw, "2024-06-01T00:00:00Z")),
+            Err((_, TransactionError::Overdraft { .. }))
+        ));
+        assert!(store.get_audit_log().is_empty());
+
+        assert!(store
+            .apply_transaction(at(deposit(2, 3, "1"), "2024-06-02T00:00:00Z"))
+            .is_ok());
+        // The dispute is resolved once its expiry is noticed, not back at its deadline
+        let held_at = |at: &str| {
+            let balances = store.get_balances_at(ClientId(1), at.parse().unwrap());
+            balances.unwrap()[&None].held
+        };
+        assert_eq!(held_at("2024-05-20T00:00:00Z"), 10.into());
+        assert_eq!(held_at("2024-06-02T00:00:00Z"), 0.into());
+        assert_eq!(
+            store.get_accounts().unwrap()[&ClientId(1)].get_held(),
+            0.into()
+        );
+        let audit_log = store.get_audit_log();
+        assert_eq!(audit_log.len(), 1);
+        assert_eq!(audit_log[0].transaction_type, TransactionType::Resolve);
+        assert_eq!(audit_log[0].reason.as_deref(), Some(DISPUTE_EXPIRED));
+        let record = store.get_transactions().get(TransactionId(1)).unwrap();
+        assert_eq!(record.unwrap().state(), DisputeState::Resolved);
+    }
+
+    #[test]
+    fn recovers_from_the_journal_and_resumes_input() {
+        let path = temp_path("store-journal");
+
+        let mut store = Store::new()
+            .with_journal(Journal::open(&path).unwrap())
+            .unwrap();
+        store.set_source("input.csv".to_string());
+        assert!(store.apply_input(deposit(1, 1, "10"), 10).is_ok());
+        assert!(store.apply_input(deposit(1, 2, "5"), 20).is_ok());
+        assert!(store.apply_input(deposit(1, 2, "5"), 30).is_err());
+        assert!(store.apply_input(deposit(1, 3, "-1"), 40).is_err());
+
+        // A run that died here is picked up by a new store on the same journal
+        let mut store = Store::new()
+            .with_journal(Journal::open(&path).unwrap())
+            .unwrap();
+        assert_eq!(
+            store.get_accounts().unwrap()[&ClientId(1)].get_available(),
+            15.into()
+        );
+        store.set_source("input.csv".to_string());
+        assert_eq!(
+            store.apply_input(deposit(1, 1, "10"), 10).unwrap(),
+            InputOutcome::Applied
+        );
+        assert!(store.apply_input(deposit(1, 2, "5"), 20).is_ok());
+        // A row refused the first time is still reported, with the error it was refused with
+        assert!(matches!(
+            store.apply_input(deposit(1, 2, "5"), 30),
+            Err((_, TransactionError::DuplicateTransaction { .. }))
+        ));
+        // Invalid rows are never journaled, so they are rejected again
+        assert!(store.apply_input(deposit(1, 3, "-1"), 40).is_err());
+        assert!(store.apply_input(deposit(1, 4, "1"), 50).is_ok());
+        assert_eq!(
+            store.get_accounts().unwrap()[&ClientId(1)].get_available(),
+            16.into()
+        );
+
+        store.clear_journal().unwrap();
+        assert!(Journal::open(&path).unwrap().entries().unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn skips_rows_a_restored_snapshot_covers() {
+        let path = temp_path("store-snapshot-journal");
+
+        let mut store = Store::new()
+            .with_journal(Journal::open(&path).unwrap())
+            .unwrap();
+        store.set_source("input.csv".to_string());
+        assert!(store.apply_input(deposit(1, 1, "10"), 10).is_ok());
+        assert!(store.apply_input(deposit(1, 1, "10"), 20).is_err());
+        // A run that died after saving its snapshot, but before clearing the journal
+        let snapshot = store.snapshot().unwrap();
+
+        let mut store = Store::new()
+            .restore(snapshot)
+            .unwrap()
+            .with_journal(Journal::open(&path).unwrap())
+            .unwrap();
+        store.set_source("input.csv".to_string());
+        assert_eq!(
+            store.apply_input(deposit(1, 1, "10"), 10).unwrap(),
+            InputOutcome::Skipped
+        );
+        assert!(store.apply_input(deposit(1, 2, "5"), 30).is_ok());
+        assert_eq!(
+            store.get_accounts().unwrap()[&ClientId(1)].get_available(),
+            15.into()
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
 }
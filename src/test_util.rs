@@ -0,0 +1,34 @@
+//! Fixtures shared by the tests of several modules
+
+use std::{env, fs, path::PathBuf, process};
+
+use crate::model::{
+    id::{client_id::ClientId, transaction_id::TransactionId},
+    transaction::Transaction,
+    transaction_type::TransactionType,
+};
+
+/// A transaction of client 1, in the base currency and without a timestamp
+pub fn transaction(transaction_type: TransactionType, tx: u32, amount: Option<u32>) -> Transaction {
+    Transaction {
+        transaction_type,
+        client: ClientId(1),
+        tx: TransactionId(tx),
+        amount: amount.map(Into::into),
+        destination: None,
+        currency: None,
+        to_currency: None,
+        rate: None,
+        reason: None,
+        timestamp: None,
+    }
+}
+
+/// A file or directory in the temp directory for this test process, removing anything an
+/// earlier run left
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("pseudo-bank-{}-{}", name, process::id()));
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_dir_all(&path);
+    path
+}